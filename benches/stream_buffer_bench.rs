@@ -0,0 +1,196 @@
+use futures::{channel::mpsc, executor::block_on, prelude::*};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use spbench::*;
+use std::thread;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let states = make_states(100, 1000);
+    c.bench_function("emit SPState", |b| {
+        b.iter_batched(
+            || states.clone(),
+            |xs| black_box(emit_states(xs)),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("emit SPState with clone", |b| {
+        b.iter_batched(
+            || states.clone(),
+            |xs| black_box(emit_states_with_clone(xs)),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+pub fn throughput_benchmark(c: &mut Criterion) {
+    bench_throughput(c, "usize", 1000, |i| i);
+    let small = make_state(10);
+    bench_throughput(c, "SPState 10", 1000, move |_| small.clone());
+    let large = make_state(1000);
+    bench_throughput(c, "SPState 1000", 100, move |_| large.clone());
+}
+
+pub fn latency_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("latency");
+
+    let (mut tx, mut rx) = mpsc::channel(10);
+    group.bench_function("channel idle", |b| {
+        b.iter(|| block_on(async {
+            tx.send(1).await.unwrap();
+            black_box(rx.next().await)
+        }))
+    });
+
+    let (mut tx, rx) = mpsc::channel(10);
+    let mut buffer = StreamBuffer::new(rx, strategies::Sum);
+    group.bench_function("StreamBuffer idle", |b| {
+        b.iter(|| block_on(async {
+            tx.send(1).await.unwrap();
+            black_box(buffer.next().await)
+        }))
+    });
+
+    // The time until the consumer has the latest state, when 100 states
+    // are waiting in the channel.
+    let state = make_state(10);
+    group.bench_function("channel latest of 100", |b| {
+        b.iter_batched(
+            || backlog(&state, 100),
+            |(tx, rx)| {
+                let latest = block_on(rx.take(100).fold(None, |_, x| future::ready(Some(x))));
+                drop(tx);
+                black_box(latest)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("StreamBuffer latest of 100", |b| {
+        b.iter_batched(
+            || backlog(&state, 100),
+            |(tx, rx)| {
+                let latest = block_on(StreamBuffer::from_mergeable(rx).next());
+                drop(tx);
+                black_box(latest)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark, throughput_benchmark, latency_benchmark);
+criterion_main!(benches);
+
+fn make_states(no_of_states: usize, size: usize) -> Vec<SPState> {
+    let values: Vec<(SPPath, SPValue)> = (0..size)
+        .map(|i| (SPPath::from_string(&i.to_string()), i.to_string().to_spvalue()))
+        .collect();
+    (0..no_of_states)
+        .map(|_| SPState::new_from_values(&values))
+        .collect()
+}
+
+fn make_state(size: usize) -> SPState {
+    make_states(1, size).pop().unwrap()
+}
+
+fn no_merge(_: &mut SPState, _: &SPState) -> bool {
+    false
+}
+
+/// Every state is emitted by moving it out of the buffer
+fn emit_states(states: Vec<SPState>) -> usize {
+    block_on(async {
+        let buffer = StreamBuffer::new(stream::iter(states), no_merge);
+        buffer.fold(0, |k, s| future::ready(k + s.id().as_bytes()[0] as usize)).await
+    })
+}
+
+/// Every state is cloned when emitted, as the buffer did before it moved
+/// the buffered item out
+fn emit_states_with_clone(states: Vec<SPState>) -> usize {
+    block_on(async {
+        let buffer = StreamBuffer::new(stream::iter(states), no_merge);
+        buffer
+            .map(|s| s.clone())
+            .fold(0, |k, s| future::ready(k + s.id().as_bytes()[0] as usize))
+            .await
+    })
+}
+
+type Numbered<P> = (usize, P);
+
+/// Sends no_of_items through a plain channel and through a StreamBuffer,
+/// using 1 or 4 producer threads. Every n:th item is not merged, to get
+/// different merge ratios.
+fn bench_throughput<P, M>(c: &mut Criterion, name: &str, no_of_items: usize, make: M)
+where
+    P: Mergeable + Clone + Send + 'static,
+    M: Fn(usize) -> P
+{
+    let mut group = c.benchmark_group(format!("throughput {}", name));
+    group.throughput(Throughput::Elements(no_of_items as u64));
+    group.sample_size(20);
+    for producers in &[1, 4] {
+        let items: Vec<Vec<Numbered<P>>> = (0..*producers)
+            .map(|p| (p..no_of_items).step_by(*producers).map(|i| (i, make(i))).collect())
+            .collect();
+        group.bench_with_input(BenchmarkId::new("channel", producers), &items, |b, items| {
+            b.iter_batched(
+                || items.clone(),
+                |xs| black_box(read_channel(xs)),
+                BatchSize::LargeInput,
+            )
+        });
+        for (ratio, every) in &[("0%", 1), ("50%", 2), ("100%", usize::MAX)] {
+            let id = BenchmarkId::new(format!("StreamBuffer merge {}", ratio), producers);
+            group.bench_with_input(id, &items, |b, items| {
+                b.iter_batched(
+                    || items.clone(),
+                    |xs| black_box(read_buffer(xs, *every)),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Spawns a thread per list of items that sends them to the channel.
+fn produce<T: Send + 'static>(items: Vec<Vec<T>>) -> (Vec<thread::JoinHandle<()>>, mpsc::Receiver<T>) {
+    let (tx, rx) = mpsc::channel(100);
+    let producers = items.into_iter().map(|xs| {
+        let mut tx = tx.clone();
+        thread::spawn(move || block_on(async move {
+            for x in xs {
+                tx.send(x).await.unwrap();
+            }
+        }))
+    }).collect();
+    (producers, rx)
+}
+
+fn read_channel<P: Send + 'static>(items: Vec<Vec<Numbered<P>>>) -> usize {
+    let (producers, rx) = produce(items);
+    let res = block_on(rx.fold(0, |k, _| future::ready(k + 1)));
+    producers.into_iter().for_each(|p| p.join().unwrap());
+    res
+}
+
+fn read_buffer<P: Mergeable + Send + 'static>(items: Vec<Vec<Numbered<P>>>, every: usize) -> usize {
+    let (producers, rx) = produce(items);
+    let buffer = StreamBuffer::new(rx, move |b: &mut Numbered<P>, x: &Numbered<P>| {
+        !x.0.is_multiple_of(every) && b.1.merge(&x.1)
+    });
+    let res = block_on(buffer.fold(0, |k, _| future::ready(k + 1)));
+    producers.into_iter().for_each(|p| p.join().unwrap());
+    res
+}
+
+/// A channel with no_of_items states waiting in it.
+fn backlog(state: &SPState, no_of_items: usize) -> (mpsc::Sender<SPState>, mpsc::Receiver<SPState>) {
+    let (mut tx, rx) = mpsc::channel(no_of_items);
+    for _ in 0..no_of_items {
+        tx.try_send(state.clone()).unwrap();
+    }
+    (tx, rx)
+}
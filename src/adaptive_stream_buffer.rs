@@ -0,0 +1,178 @@
+//! The AdaptiveStreamBuffer only folds items when the consumer falls behind
+
+use super::*;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task;
+use pin_project_lite::pin_project;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pin_project! {
+    /// AdaptiveStreamBuffer is a stream buffer that returns the items one by
+    /// one as long as the consumer keeps up with the stream. The ready items
+    /// are read into a backlog, and when the backlog is longer than
+    /// max_backlog, or the oldest item has been in the backlog longer than
+    /// max_age, the items in the backlog are folded using the merger.
+    ///
+    /// If the merge fn returns false, the buffer is returned and the rest of
+    /// the backlog is kept for the next poll.
+    ///
+    /// # Example
+    /// ```
+    /// use futures::{executor::block_on, prelude::*};
+    /// use spbench::*;
+    ///
+    /// let xs = stream::iter(vec!(1, 2, 3, 4, 5));
+    /// let buffer = AdaptiveStreamBuffer::new(xs, strategies::Sum).max_backlog(2);
+    ///
+    /// block_on(async move {
+    ///     let res: Vec<usize> = buffer.collect().await;
+    ///     assert_eq!(res, vec!(6, 4, 5));
+    /// });
+    /// ```
+    pub struct AdaptiveStreamBuffer<S, F> where S: Stream, F: Merger<S::Item> {
+        #[pin] stream: S,
+        merger: F,
+        backlog: VecDeque<(Instant, S::Item)>,
+        max_backlog: usize,
+        max_age: Option<Duration>,
+        clock: Arc<dyn testing::Clock>,
+        done: bool,
+    }
+}
+
+impl<S, F> AdaptiveStreamBuffer<S, F> where S: Stream, F: Merger<S::Item> {
+    /// Creates a new AdaptiveStreamBuffer that reads from the stream and
+    /// folds the backlog using merger when it is longer than 16 items.
+    pub fn new(stream: S, merger: F) -> AdaptiveStreamBuffer<S, F> {
+        AdaptiveStreamBuffer {
+            stream,
+            merger,
+            backlog: VecDeque::new(),
+            max_backlog: 16,
+            max_age: None,
+            clock: Arc::new(testing::SystemClock),
+            done: false,
+        }
+    }
+
+    /// Folds the backlog when it is longer than max_backlog items.
+    pub fn max_backlog(mut self, max_backlog: usize) -> Self {
+        self.max_backlog = max_backlog;
+        self
+    }
+
+    /// Folds the backlog when the oldest item was read from the stream more
+    /// than max_age ago.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Uses the clock for max_age instead of the wall clock. Use this with a 
+    /// testing::ManualClock in tests.
+    pub fn with_clock<C>(mut self, clock: C) -> Self 
+    where 
+        C: testing::Clock + 'static 
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+impl<S, F> Stream for AdaptiveStreamBuffer<S, F> where S: Stream, F: Merger<S::Item> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        while !*this.done && this.backlog.len() <= *this.max_backlog {
+            match this.stream.as_mut().poll_next(cx) {
+                task::Poll::Ready(Some(x)) => this.backlog.push_back((this.clock.now(), x)),
+                task::Poll::Ready(None) => *this.done = true,
+                task::Poll::Pending => break,
+            }
+        }
+
+        let lagging = this.backlog.len() > *this.max_backlog || match (this.backlog.front(), this.max_age) {
+            (Some((t, _)), Some(max_age)) => this.clock.now().saturating_duration_since(*t) >= *max_age,
+            _ => false,
+        };
+        let mut buffer = match this.backlog.pop_front() {
+            Some((_, x)) => x,
+            None if *this.done => return task::Poll::Ready(None),
+            None => return task::Poll::Pending,
+        };
+        if lagging {
+            while let Some((_, x)) = this.backlog.front() {
+                if !this.merger.merge(&mut buffer, x) {
+                    break;
+                }
+                this.backlog.pop_front();
+            }
+        }
+        task::Poll::Ready(Some(buffer))
+    }
+}
+
+
+#[cfg(test)]
+mod tests_adaptive_stream_buffer {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use crate::testing::*;
+    use futures::channel::mpsc::channel;
+    use std::task::Poll;
+
+    #[test]
+    fn pass_through_when_keeping_up() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = AdaptiveStreamBuffer::new(rx, strategies::Sum).max_backlog(2);
+            tx.send(1).await.unwrap();
+            tx.send(2).await.unwrap();
+            assert_eq!(buffer.next().await, Some(1));
+            assert_eq!(buffer.next().await, Some(2));
+            tx.send(3).await.unwrap();
+            drop(tx);
+            assert_eq!(buffer.next().await, Some(3));
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[test]
+    fn merge_when_backlog_is_long() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = AdaptiveStreamBuffer::new(rx, |x: &mut usize, y: &usize| {
+                *y != 0 && { *x += y; true }
+            }).max_backlog(2);
+            for x in &[1, 2, 0, 3] {
+                tx.send(*x).await.unwrap();
+            }
+            assert_eq!(buffer.next().await, Some(3));
+            assert_eq!(buffer.next().await, Some(0));
+            assert_eq!(buffer.next().await, Some(3));
+        });
+    }
+
+    #[test]
+    fn merge_when_backlog_is_old() {
+        let clock = ManualClock::new();
+        let (script, source) = scripted();
+        let mut buffer = AdaptiveStreamBuffer::new(source, strategies::Sum)
+            .max_age(Duration::from_millis(10))
+            .with_clock(clock.clone());
+        script.push_all(vec!(1, 2));
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(1)));
+        clock.advance(Duration::from_millis(5));
+        script.push(3);
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(2)));
+        clock.advance(Duration::from_millis(10));
+        script.push(4);
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(7)));
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+    }
+}
@@ -0,0 +1,143 @@
+//! Flush items that forces the StreamBuffer to return the buffer
+
+use super::*;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task;
+use pin_project_lite::pin_project;
+
+/// An item in a stream that can include barriers. When a Flush is received,
+/// the buffer is returned directly, and items after the Flush are never 
+/// merged with items before it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Item<T> {
+    Data(T),
+    Flush,
+}
+
+impl<T> From<T> for Item<T> {
+    fn from(x: T) -> Self {
+        Item::Data(x)
+    }
+}
+
+/// Merges the data using the merger. Never merges a Flush.
+#[derive(Debug, Clone, Copy)]
+pub struct BarrierMerger<F> {
+    merger: F,
+}
+
+impl<F> BarrierMerger<F> {
+    pub fn new(merger: F) -> BarrierMerger<F> {
+        BarrierMerger { merger }
+    }
+}
+
+impl<T, F> Merger<Item<T>> for BarrierMerger<F> where F: Merger<T> {
+    fn merge(&mut self, buffer: &mut Item<T>, item: &Item<T>) -> bool {
+        match (buffer, item) {
+            (Item::Data(b), Item::Data(x)) => self.merger.merge(b, x),
+            _ => false,
+        }
+    }
+}
+
+pin_project! {
+    /// BarrierStreamBuffer is a StreamBuffer that reads a stream of Item, where 
+    /// Item::Flush is used as a barrier. The data is folded using the merge fn
+    /// and when a Flush arrives, the buffer is returned directly, also if the
+    /// StreamBuffer is waiting for more items with debounce or max_latency.
+    /// 
+    /// # Example
+    /// ```
+    /// use futures::{executor::block_on, prelude::*};
+    /// use spbench::*;
+    ///
+    /// let xs = stream::iter(vec!(Item::Data(1), Item::Data(2), Item::Flush, Item::Data(3)));
+    /// let buffer = BarrierStreamBuffer::new(xs, strategies::Sum);
+    ///
+    /// block_on(async move {
+    ///     let res: Vec<usize> = buffer.collect().await;
+    ///     assert_eq!(res, vec!(3, 3));
+    /// });
+    /// ```
+    pub struct BarrierStreamBuffer<S, F> where S: Stream, F: Merger<S::Item> {
+        #[pin] buffer: StreamBuffer<S, F>,
+    }
+}
+
+impl<S, T, F> BarrierStreamBuffer<S, BarrierMerger<F>> where S: Stream<Item = Item<T>>, F: Merger<T> {
+    /// Creates a new BarrierStreamBuffer that reads from the stream and folds 
+    /// the data between the barriers using merger.
+    pub fn new(stream: S, merger: F) -> BarrierStreamBuffer<S, BarrierMerger<F>> {
+        BarrierStreamBuffer::from_buffer(StreamBuffer::new(stream, BarrierMerger::new(merger)))
+    }
+
+    /// Creates a new BarrierStreamBuffer from a StreamBuffer. Use this to 
+    /// configure the StreamBuffer, e.g. with debounce.
+    pub fn from_buffer(buffer: StreamBuffer<S, BarrierMerger<F>>) -> BarrierStreamBuffer<S, BarrierMerger<F>> {
+        BarrierStreamBuffer { buffer }
+    }
+}
+
+impl<S, T, F> Stream for BarrierStreamBuffer<S, BarrierMerger<F>> where S: Stream<Item = Item<T>>, F: Merger<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            match this.buffer.as_mut().poll_next(cx) {
+                task::Poll::Ready(Some(Item::Data(x))) => return task::Poll::Ready(Some(x)),
+                task::Poll::Ready(Some(Item::Flush)) => {},
+                task::Poll::Ready(None) => return task::Poll::Ready(None),
+                task::Poll::Pending => return task::Poll::Pending,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests_barrier {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use futures::channel::mpsc::channel;
+
+    #[test]
+    fn flush() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = rx.merge_with_barriers(|x: &mut usize, y: &usize| {
+                *x += y;
+                true
+            });
+            tx.send(Item::Data(1)).await.unwrap();
+            tx.send(Item::Flush).await.unwrap();
+            tx.send(Item::Flush).await.unwrap();
+            tx.send(2.into()).await.unwrap();
+            tx.send(3.into()).await.unwrap();
+            assert_eq!(buffer.next().await, Some(1));
+            assert_eq!(buffer.next().await, Some(5));
+            drop(tx);
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn flush_timed_buffer() {
+        use std::time::Duration;
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let buffer = StreamBuffer::new(rx, BarrierMerger::new(strategies::Sum))
+                .debounce(Duration::from_secs(10));
+            let mut buffer = BarrierStreamBuffer::from_buffer(buffer);
+            tx.send(Item::Data(1)).await.unwrap();
+            tx.send(Item::Data(2)).await.unwrap();
+            tx.send(Item::Flush).await.unwrap();
+            let res = timeout(Duration::from_secs(1), buffer.next()).await;
+            assert_eq!(res, Ok(Some(3)));
+        });
+    }
+}
@@ -0,0 +1,163 @@
+//! The BlockingStreamBuffer folds items from a channel on a plain thread
+
+use super::*;
+use std::sync::mpsc;
+
+/// A channel that can be read from a plain thread.
+pub trait BlockingSource {
+    type Item;
+
+    /// Blocks until an item is received. Returns None when the channel is
+    /// closed.
+    fn recv(&mut self) -> Option<Self::Item>;
+
+    /// Returns an item if one is ready, without blocking.
+    fn try_recv(&mut self) -> Option<Self::Item>;
+}
+
+impl<T> BlockingSource for mpsc::Receiver<T> {
+    type Item = T;
+
+    fn recv(&mut self) -> Option<T> {
+        mpsc::Receiver::recv(self).ok()
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        mpsc::Receiver::try_recv(self).ok()
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+impl<T> BlockingSource for crossbeam_channel::Receiver<T> {
+    type Item = T;
+
+    fn recv(&mut self) -> Option<T> {
+        crossbeam_channel::Receiver::recv(self).ok()
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        crossbeam_channel::Receiver::try_recv(self).ok()
+    }
+}
+
+/// BlockingStreamBuffer is an Iterator that folds the ready items in a
+/// channel using the merge fn, in the same way as the StreamBuffer. The
+/// iterator blocks until an item arrives, and then folds all items that
+/// are ready in the channel into it.
+///
+/// If the merge fn returns false, the buffer is returned and the item is
+/// used as the next buffer. The iterator ends when the channel is closed.
+///
+/// # Example
+/// ```
+/// use std::sync::mpsc::channel;
+/// use spbench::*;
+///
+/// let (tx, rx) = channel();
+/// let mut buffer = BlockingStreamBuffer::new(rx, strategies::Sum);
+/// tx.send(1).unwrap();
+/// tx.send(2).unwrap();
+/// tx.send(3).unwrap();
+/// drop(tx);
+/// assert_eq!(buffer.next(), Some(6));
+/// assert_eq!(buffer.next(), None);
+/// ```
+pub struct BlockingStreamBuffer<R, F> where R: BlockingSource, F: Merger<R::Item> {
+    source: R,
+    merger: F,
+    pending: Option<R::Item>,
+}
+
+impl<R, F> BlockingStreamBuffer<R, F> where R: BlockingSource, F: Merger<R::Item> {
+    /// Creates a new BlockingStreamBuffer that reads from the channel and
+    /// folds the ready items using merger.
+    pub fn new(source: R, merger: F) -> BlockingStreamBuffer<R, F> {
+        BlockingStreamBuffer {
+            source,
+            merger,
+            pending: None,
+        }
+    }
+}
+
+impl<R, F> Iterator for BlockingStreamBuffer<R, F> where R: BlockingSource, F: Merger<R::Item> {
+    type Item = R::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = match self.pending.take() {
+            Some(x) => x,
+            None => self.source.recv()?,
+        };
+        while let Some(x) = self.source.try_recv() {
+            if !self.merger.merge(&mut buffer, &x) {
+                self.pending = Some(x);
+                break;
+            }
+        }
+        Some(buffer)
+    }
+}
+
+
+#[cfg(test)]
+mod tests_blocking_stream_buffer {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn sum_until_zero(x: &mut usize, y: &usize) -> bool {
+        *x += y;
+        *x != 0
+    }
+
+    #[test]
+    fn merge_waiting_items() {
+        let (tx, rx) = mpsc::channel();
+        let mut buffer = BlockingStreamBuffer::new(rx, sum_until_zero);
+        for x in 1..5 {
+            tx.send(x).unwrap();
+        }
+        assert_eq!(buffer.next(), Some(10));
+        tx.send(5).unwrap();
+        drop(tx);
+        assert_eq!(buffer.next(), Some(5));
+        assert_eq!(buffer.next(), None);
+    }
+
+    #[test]
+    fn no_merge_when_false() {
+        let (tx, rx) = mpsc::channel();
+        let buffer = BlockingStreamBuffer::new(rx, |_: &mut usize, _: &usize| false);
+        for x in 1..5 {
+            tx.send(x).unwrap();
+        }
+        drop(tx);
+        assert_eq!(buffer.collect::<Vec<usize>>(), vec!(1, 2, 3, 4));
+    }
+
+    #[test]
+    fn blocks_until_item() {
+        let (tx, rx) = mpsc::channel();
+        let mut buffer = BlockingStreamBuffer::new(rx, sum_until_zero);
+        let producer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx.send(1).unwrap();
+        });
+        assert_eq!(buffer.next(), Some(1));
+        producer.join().unwrap();
+        assert_eq!(buffer.next(), None);
+    }
+
+    #[cfg(feature = "crossbeam")]
+    #[test]
+    fn merge_from_crossbeam() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut buffer = BlockingStreamBuffer::new(rx, sum_until_zero);
+        for x in 1..5 {
+            tx.send(x).unwrap();
+        }
+        drop(tx);
+        assert_eq!(buffer.next(), Some(10));
+        assert_eq!(buffer.next(), None);
+    }
+}
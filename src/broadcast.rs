@@ -0,0 +1,228 @@
+//! Broadcast the merged items of a stream to multiple subscribers
+
+use super::*;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::{future, task};
+use pin_project_lite::pin_project;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+pin_project! {
+    /// Broadcast reads the items from a stream, e.g. a StreamBuffer, and sends 
+    /// them to all subscribers. Each subscriber has its own buffer, so a slow 
+    /// subscriber does not slow down the others. Instead, the items waiting 
+    /// for a slow subscriber are folded using the merge fn.
+    /// 
+    /// Broadcast is a future that reads from the stream until it ends, so it 
+    /// should be spawned or awaited. 
+    /// 
+    /// # Example
+    /// ```
+    /// use futures::{channel::mpsc::channel, executor::block_on, prelude::*};
+    /// use spbench::*;
+    ///
+    /// let (mut tx, rx) = channel(10);
+    /// let hub = StreamBuffer::new(rx, strategies::Sum).broadcast();
+    /// let mut fast = hub.subscribe();
+    /// let mut slow = hub.subscribe();
+    ///
+    /// block_on(future::join(hub, async move {
+    ///     tx.send(1).await.unwrap();
+    ///     assert_eq!(fast.next().await, Some(1));
+    ///     tx.send(2).await.unwrap();
+    ///     assert_eq!(fast.next().await, Some(2));
+    ///     drop(tx);
+    ///     assert_eq!(fast.next().await, None);
+    ///     assert_eq!(slow.next().await, Some(3));
+    ///     assert_eq!(slow.next().await, None);
+    /// }));
+    /// ```
+    pub struct Broadcast<S> where S: Stream {
+        #[pin] stream: S,
+        hub: Arc<Mutex<Hub<S::Item>>>,
+    }
+}
+
+struct Hub<T> {
+    merger: Box<dyn Merger<T> + Send>,
+    subscribers: HashMap<usize, Slot<T>>,
+    next_id: usize,
+    closed: bool,
+}
+
+/// The items waiting for a subscriber
+struct Slot<T> {
+    items: VecDeque<T>,
+    waker: Option<task::Waker>,
+}
+
+impl<T> Hub<T> {
+    fn subscribe(hub: &Arc<Mutex<Hub<T>>>) -> Subscriber<T> {
+        let mut h = hub.lock().unwrap();
+        let id = h.next_id;
+        h.next_id += 1;
+        h.subscribers.insert(id, Slot { items: VecDeque::new(), waker: None });
+        Subscriber { id, hub: hub.clone() }
+    }
+}
+
+impl<S> Broadcast<S> where S: Stream, S::Item: Clone {
+    /// Creates a new Broadcast that sends the items of the stream to all 
+    /// subscribers, and folds the items waiting for a subscriber using merger.
+    pub fn new<F>(stream: S, merger: F) -> Broadcast<S> where F: Merger<S::Item> + Send + 'static {
+        let hub = Hub {
+            merger: Box::new(merger),
+            subscribers: HashMap::new(),
+            next_id: 0,
+            closed: false,
+        };
+        Broadcast {
+            stream,
+            hub: Arc::new(Mutex::new(hub)),
+        }
+    }
+
+    /// Returns a new subscriber that will get all items from now on.
+    pub fn subscribe(&self) -> Subscriber<S::Item> {
+        Hub::subscribe(&self.hub)
+    }
+}
+
+impl<S, F> StreamBuffer<S, F> where S: Stream, S::Item: Clone, F: Merger<S::Item> + Clone + Send + 'static {
+    /// Broadcasts the merged items to multiple subscribers. Items waiting for a
+    /// slow subscriber are folded using the same merge fn.
+    pub fn broadcast(self) -> Broadcast<StreamBuffer<S, F>> {
+        let merger = self.merger().clone();
+        Broadcast::new(self, merger)
+    }
+}
+
+impl<S> future::Future for Broadcast<S> where S: Stream, S::Item: Clone {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<()> {
+        let mut this = self.project();
+        loop {
+            match this.stream.as_mut().poll_next(cx) {
+                task::Poll::Ready(Some(x)) => {
+                    let mut hub = this.hub.lock().unwrap();
+                    let Hub { merger, subscribers, .. } = &mut *hub;
+                    for slot in subscribers.values_mut() {
+                        let merged = slot.items
+                            .back_mut()
+                            .map(|b| merger.merge(b, &x))
+                            .unwrap_or(false);
+                        if !merged {
+                            slot.items.push_back(x.clone());
+                        }
+                        if let Some(w) = slot.waker.take() {
+                            w.wake();
+                        }
+                    }
+                }
+                task::Poll::Ready(None) => {
+                    let mut hub = this.hub.lock().unwrap();
+                    hub.closed = true;
+                    for slot in hub.subscribers.values_mut() {
+                        if let Some(w) = slot.waker.take() {
+                            w.wake();
+                        }
+                    }
+                    return task::Poll::Ready(());
+                }
+                task::Poll::Pending => return task::Poll::Pending,
+            }
+        }
+    }
+}
+
+/// A subscriber to a Broadcast. Ends when the broadcasted stream has ended 
+/// and all items have been received.
+pub struct Subscriber<T> {
+    id: usize,
+    hub: Arc<Mutex<Hub<T>>>,
+}
+
+impl<T> Subscriber<T> {
+    /// Returns a new subscriber to the same Broadcast, that will get all 
+    /// items from now on.
+    pub fn resubscribe(&self) -> Subscriber<T> {
+        Hub::subscribe(&self.hub)
+    }
+}
+
+impl<T> Stream for Subscriber<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
+        let mut hub = self.hub.lock().unwrap();
+        let closed = hub.closed;
+        let slot = hub.subscribers.get_mut(&self.id).unwrap();
+        if let Some(x) = slot.items.pop_front() {
+            task::Poll::Ready(Some(x))
+        } else if closed {
+            task::Poll::Ready(None)
+        } else {
+            slot.waker = Some(cx.waker().clone());
+            task::Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        if let Ok(mut hub) = self.hub.lock() {
+            hub.subscribers.remove(&self.id);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests_broadcast {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use futures::channel::mpsc::channel;
+
+    #[test]
+    fn slow_subscriber() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let hub = Broadcast::new(rx, strategies::AppendVec);
+            let mut fast = hub.subscribe();
+            let mut slow = hub.subscribe();
+            spawn(hub);
+
+            for x in 1..5 {
+                tx.send(vec!(x)).await.unwrap();
+                assert_eq!(fast.next().await, Some(vec!(x)));
+            }
+            let mut late = slow.resubscribe();
+            tx.send(vec!(5)).await.unwrap();
+            drop(tx);
+
+            let res: Vec<Vec<usize>> = fast.collect().await;
+            assert_eq!(res, vec!(vec!(5)));
+            assert_eq!(slow.next().await, Some(vec!(1, 2, 3, 4, 5)));
+            assert_eq!(late.next().await, Some(vec!(5)));
+            assert_eq!(slow.next().await, None);
+        });
+    }
+
+    #[test]
+    fn no_merge_when_false() {
+        block_on(async {
+            let xs = stream::iter(vec!(1, 2, 0, 3));
+            let until_zero = strategies::MergeUntil::new(strategies::Sum, |x: &usize| *x == 0);
+            let hub = Broadcast::new(xs, until_zero);
+            let a = hub.subscribe();
+            let b = hub.subscribe();
+            hub.await;
+            let res: Vec<usize> = a.collect().await;
+            assert_eq!(res, vec!(3, 3));
+            drop(b);
+        });
+    }
+}
@@ -0,0 +1,357 @@
+//! A channel that is read by a StreamBuffer and that handles overflow 
+//! without blocking the producers
+
+use super::*;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task;
+use futures::future;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// What to do when an item is sent and the channel is full.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OverflowPolicy {
+    /// The sender waits until there is room in the channel.
+    Block,
+    /// The oldest item in the channel is dropped.
+    DropOldest,
+    /// The new item is dropped.
+    DropNewest,
+    /// The new item is merged into the newest item in the channel using the 
+    /// merge fn. If the merge fn returns false, the sender waits as with 
+    /// Block, so no item is lost.
+    Conflate,
+}
+
+/// Creates a bounded channel with an overflow policy, where the receiving side
+/// is a StreamBuffer that folds the items using merger. Only the Block and 
+/// Conflate policies make the senders wait when the channel is full.
+/// 
+/// The channel uses a clone of merger for Conflate, so a merger with state 
+/// does not share that state with the StreamBuffer.
+/// 
+/// # Panics
+/// If cap is zero.
+/// 
+/// # Example
+/// ```
+/// use futures::{executor::block_on, prelude::*};
+/// use spbench::*;
+///
+/// let (tx, mut rx) = buffer_channel(2, OverflowPolicy::DropOldest, strategies::Sum);
+///
+/// block_on(async move {
+///     for x in 1..5 {
+///         tx.send(x).await;  // never waits
+///     }
+///     drop(tx);
+///     assert_eq!(rx.next().await, Some(7));
+/// });
+/// ```
+pub fn buffer_channel<T, F>(
+    cap: usize, 
+    policy: OverflowPolicy, 
+    merger: F
+) -> (BufferSender<T>, StreamBuffer<BufferReceiver<T>, F>) 
+where 
+    F: Merger<T> + Clone + Send + 'static 
+{
+    assert!(cap > 0, "The capacity of the buffer channel must be larger than 0");
+    let queue = Queue {
+        items: VecDeque::with_capacity(cap),
+        cap,
+        policy,
+        merger: Box::new(merger.clone()),
+        receiver: None,
+        blocked: VecDeque::new(),
+        senders: 1,
+        closed: false,
+    };
+    let shared = Arc::new(Mutex::new(queue));
+    let tx = BufferSender { shared: shared.clone() };
+    let rx = BufferReceiver { shared };
+    (tx, StreamBuffer::with_is_empty(rx, merger, BufferReceiver::is_empty))
+}
+
+struct Queue<T> {
+    items: VecDeque<T>,
+    cap: usize,
+    policy: OverflowPolicy,
+    merger: Box<dyn Merger<T> + Send>,
+    receiver: Option<task::Waker>,
+    blocked: VecDeque<task::Waker>,
+    senders: usize,
+    closed: bool,
+}
+
+impl<T> Queue<T> {
+    /// No more items can be sent, and the blocked senders are woken.
+    fn close(&mut self) {
+        self.closed = true;
+        self.blocked.drain(..).for_each(|w| w.wake());
+    }
+
+    /// Adds the item to the queue based on the policy. Returns the item if it
+    /// has to wait or if the receiver is dropped or closed.
+    fn push(&mut self, item: T) -> Result<(), T> {
+        if self.closed {
+            return Err(item);
+        }
+        if self.items.len() < self.cap {
+            self.items.push_back(item);
+        } else {
+            match self.policy {
+                OverflowPolicy::Block => return Err(item),
+                OverflowPolicy::DropNewest => return Ok(()),
+                OverflowPolicy::DropOldest => {
+                    self.items.pop_front();
+                    self.items.push_back(item);
+                }
+                OverflowPolicy::Conflate => {
+                    let merger = &mut self.merger;
+                    let merged = self.items
+                        .back_mut()
+                        .map(|b| merger.merge(b, &item))
+                        .unwrap_or(false);
+                    if !merged {
+                        return Err(item);
+                    }
+                }
+            }
+        }
+        if let Some(w) = self.receiver.take() {
+            w.wake();
+        }
+        Ok(())
+    }
+}
+
+/// The sending side of a buffer channel. Can be cloned to get more senders.
+pub struct BufferSender<T> {
+    shared: Arc<Mutex<Queue<T>>>,
+}
+
+impl<T> BufferSender<T> {
+    /// Sends the item. Only waits if the channel is full and the policy is 
+    /// Block, or Conflate and the item could not be merged. If the receiver 
+    /// is dropped or closed, the item is dropped.
+    pub async fn send(&self, item: T) {
+        let mut item = Some(item);
+        future::poll_fn(|cx| {
+            let mut q = self.shared.lock().unwrap();
+            match q.push(item.take().unwrap()) {
+                Err(x) if !q.closed => {
+                    item = Some(x);
+                    q.blocked.push_back(cx.waker().clone());
+                    task::Poll::Pending
+                }
+                _ => task::Poll::Ready(()),
+            }
+        }).await
+    }
+
+    /// Sends the item without waiting. Returns the item if send would have 
+    /// waited, or if the receiver is dropped or closed.
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        self.shared.lock().unwrap().push(item)
+    }
+}
+
+impl<T> Clone for BufferSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().senders += 1;
+        BufferSender { shared: self.shared.clone() }
+    }
+}
+
+/// The lock is poisoned if the merge fn panics, and then the queue is left 
+/// as it is, since the drop may be part of the unwinding.
+impl<T> Drop for BufferSender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut q) = self.shared.lock() {
+            q.senders -= 1;
+            if q.senders == 0 {
+                if let Some(w) = q.receiver.take() {
+                    w.wake();
+                }
+            }
+        }
+    }
+}
+
+/// The receiving side of a buffer channel. Ends when all senders are dropped
+/// or the receiver is closed, and the channel is empty.
+pub struct BufferReceiver<T> {
+    shared: Arc<Mutex<Queue<T>>>,
+}
+
+impl<T> BufferReceiver<T> {
+    pub fn is_empty(&self) -> bool {
+        self.shared.lock().unwrap().items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.lock().unwrap().items.len()
+    }
+}
+
+impl<T> Stream for BufferReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
+        let mut q = self.shared.lock().unwrap();
+        if let Some(x) = q.items.pop_front() {
+            // A blocked send may have been cancelled, so all are woken and
+            // the ones still waiting will try again.
+            q.blocked.drain(..).for_each(|w| w.wake());
+            task::Poll::Ready(Some(x))
+        } else if q.senders == 0 || q.closed {
+            task::Poll::Ready(None)
+        } else {
+            q.receiver = Some(cx.waker().clone());
+            task::Poll::Pending
+        }
+    }
+}
+
+impl<T> Close for BufferReceiver<T> {
+    fn close(self: Pin<&mut Self>) {
+        self.shared.lock().unwrap().close();
+    }
+}
+
+impl<T> Drop for BufferReceiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut q) = self.shared.lock() {
+            q.close();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests_buffer_channel {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use std::time::Duration;
+
+    fn no_merge(_: &mut usize, _: &usize) -> bool {
+        false
+    }
+
+    fn send_and_collect<T, F>(policy: OverflowPolicy, xs: Vec<T>, merger: F) -> Vec<T> 
+    where 
+        T: Send + 'static,
+        F: Merger<T> + Clone + Send + 'static,
+    {
+        block_on(async {
+            let (tx, rx) = buffer_channel(2, policy, merger);
+            for x in xs {
+                tx.send(x).await;
+            }
+            drop(tx);
+            rx.max_items(1).collect().await
+        })
+    }
+
+    #[test]
+    fn drop_items() {
+        assert_eq!(send_and_collect(OverflowPolicy::DropOldest, vec!(1, 2, 3, 4), no_merge), vec!(3, 4));
+        assert_eq!(send_and_collect(OverflowPolicy::DropNewest, vec!(1, 2, 3, 4), no_merge), vec!(1, 2));
+    }
+
+    #[test]
+    fn conflate() {
+        let xs = vec!(vec!(1), vec!(2), vec!(3), vec!(4));
+        let res = send_and_collect(OverflowPolicy::Conflate, xs, strategies::AppendVec);
+        assert_eq!(res, vec!(vec!(1), vec!(2, 3, 4)));
+
+        block_on(async {
+            let (tx, rx) = buffer_channel(2, OverflowPolicy::Conflate, no_merge);
+            assert_eq!(tx.try_send(1), Ok(()));
+            assert_eq!(tx.try_send(2), Ok(()));
+            assert_eq!(tx.try_send(3), Err(3));
+            let tx2 = tx.clone();
+            spawn(async move {
+                tx2.send(3).await;
+                tx2.send(4).await;
+            });
+            drop(tx);
+            let res: Vec<usize> = rx.max_items(1).collect().await;
+            assert_eq!(res, vec!(1, 2, 3, 4));
+        });
+    }
+
+    #[test]
+    fn block() {
+        block_on(async {
+            let (tx, rx) = buffer_channel(1, OverflowPolicy::Block, no_merge);
+            assert_eq!(tx.try_send(1), Ok(()));
+            assert_eq!(tx.try_send(2), Err(2));
+            let tx2 = tx.clone();
+            spawn(async move {
+                for x in 2..10 {
+                    tx2.send(x).await;
+                }
+            });
+            drop(tx);
+            let res: Vec<usize> = rx.collect().await;
+            assert_eq!(res, (1..10).collect::<Vec<usize>>());
+        });
+    }
+
+    #[test]
+    fn block_after_cancelled_send() {
+        block_on(async {
+            let (tx, mut rx) = buffer_channel(1, OverflowPolicy::Block, no_merge);
+            tx.send(1).await;
+            assert!(timeout(Duration::from_millis(10), tx.send(2)).await.is_err());
+            let tx2 = tx.clone();
+            spawn(async move {
+                tx2.send(3).await;
+            });
+            drop(tx);
+            sleep(Duration::from_millis(10)).await;
+            assert_eq!(rx.next().await, Some(1));
+            let res = timeout(Duration::from_secs(1), rx.next()).await;
+            assert_eq!(res, Ok(Some(3)));
+        });
+    }
+
+    #[test]
+    fn shutdown() {
+        block_on(async {
+            let (tx, mut rx) = buffer_channel(2, OverflowPolicy::Block, strategies::Sum);
+            tx.send(1).await;
+            tx.send(2).await;
+            rx.shutdown_handle().shutdown();
+            assert_eq!(rx.next().await, Some(3));
+            assert_eq!(tx.try_send(3), Err(3));
+            assert_eq!(rx.next().await, None);
+        });
+    }
+
+    #[test]
+    fn merge_panics() {
+        let (tx, rx) = buffer_channel(1, OverflowPolicy::Conflate, |_: &mut usize, _: &usize| -> bool {
+            panic!("Can not merge")
+        });
+        tx.try_send(1).unwrap();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || tx.try_send(2)));
+        assert!(res.is_err());
+        drop(rx);
+    }
+
+    #[test]
+    fn receiver_dropped() {
+        block_on(async {
+            let (tx, rx) = buffer_channel(1, OverflowPolicy::Block, no_merge);
+            tx.send(1).await;
+            drop(rx);
+            tx.send(2).await;
+            assert_eq!(tx.try_send(3), Err(3));
+        });
+    }
+}
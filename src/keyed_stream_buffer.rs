@@ -0,0 +1,207 @@
+//! The KeyedStreamBuffer folds items per key
+
+use super::*;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task;
+use pin_project_lite::pin_project;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+pin_project! {
+    /// KeyedStreamBuffer is a stream buffer that keeps one buffered item per
+    /// key, and only folds items that have the same key. The key is extracted
+    /// from each item by the key fn. When the stream is pending or ended, the 
+    /// buffered items are returned together with their key, in the order the
+    /// keys were first seen.
+    /// 
+    /// If the merge fn returns false, the buffered item for that key is 
+    /// returned directly and the new item is buffered instead.
+    /// 
+    /// # Example
+    /// ```
+    /// use futures::{executor::block_on, prelude::*};
+    /// use spbench::*;
+    ///
+    /// let r1 = SPPath::from_string("robot/1");
+    /// let r2 = SPPath::from_string("robot/2");
+    /// let xs = stream::iter(vec!((r1.clone(), 1), (r2.clone(), 2), (r1.clone(), 3)));
+    /// let buffer = KeyedStreamBuffer::new(
+    ///     xs,
+    ///     |x: &(SPPath, usize)| x.0.clone(),
+    ///     |x: &mut (SPPath, usize), y: &(SPPath, usize)| {
+    ///         x.1 += y.1;
+    ///         true
+    ///     }
+    /// );
+    ///
+    /// block_on(async move {
+    ///     let res: Vec<usize> = buffer.map(|(_, x)| x.1).collect().await;
+    ///     assert_eq!(res, vec!(4, 2));
+    /// });
+    /// ```
+    pub struct KeyedStreamBuffer<S, K, KF, F> 
+    where 
+        S: Stream, 
+        K: Eq, 
+        K: Hash, 
+        K: Clone, 
+        KF: FnMut(&S::Item) -> K, 
+        F: Merger<S::Item> 
+    {
+        #[pin] stream: S,
+        key: KF,
+        merger: F, 
+        buffers: HashMap<K, S::Item>,
+        order: VecDeque<K>,
+        ready: VecDeque<(K, S::Item)>,
+        done: bool,
+    }
+}
+
+impl<S, K, KF, F> KeyedStreamBuffer<S, K, KF, F> 
+where 
+    S: Stream, 
+    K: Eq + Hash + Clone, 
+    KF: FnMut(&S::Item) -> K, 
+    F: Merger<S::Item> 
+{
+    /// Creates a new KeyedStreamBuffer that reads from the stream and folds 
+    /// the ready items with the same key using merger.
+    pub fn new(stream: S, key: KF, merger: F) -> KeyedStreamBuffer<S, K, KF, F> {
+        KeyedStreamBuffer {
+            stream,
+            key,
+            merger,
+            buffers: HashMap::new(),
+            order: VecDeque::new(),
+            ready: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl<S, K, KF, F> Stream for KeyedStreamBuffer<S, K, KF, F> 
+where 
+    S: Stream, 
+    K: Eq + Hash + Clone, 
+    KF: FnMut(&S::Item) -> K, 
+    F: Merger<S::Item> 
+{
+    type Item = (K, S::Item);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if let Some(x) = this.ready.pop_front() {
+                return task::Poll::Ready(Some(x));
+            }
+            if *this.done {
+                return task::Poll::Ready(None);
+            }
+            match this.stream.as_mut().poll_next(cx) {
+                task::Poll::Ready(Some(x)) => {
+                    let k = (this.key)(&x);
+                    let merged = match this.buffers.get_mut(&k) {
+                        Some(b) => this.merger.merge(b, &x),
+                        None => {
+                            this.order.push_back(k.clone());
+                            this.buffers.insert(k, x);
+                            continue;
+                        }
+                    };
+                    if !merged {
+                        let old = this.buffers.insert(k.clone(), x).unwrap();
+                        this.order.retain(|o| o != &k);
+                        this.order.push_back(k.clone());
+                        this.ready.push_back((k, old));
+                    }
+                }
+                task::Poll::Pending if this.order.is_empty() => {
+                    return task::Poll::Pending;
+                }
+                next => {
+                    *this.done = next.is_ready();
+                    let buffers = &mut this.buffers;
+                    this.ready.extend(this.order.drain(..).map(|k| {
+                        let x = buffers.remove(&k).unwrap();
+                        (k, x)
+                    }));
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests_keyed_stream_buffer {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use futures::channel::mpsc::channel;
+
+    fn sum(x: &mut (SPPath, usize), y: &(SPPath, usize)) -> bool {
+        x.1 += y.1;
+        true
+    }
+
+    fn key(x: &(SPPath, usize)) -> SPPath {
+        x.0.clone()
+    }
+
+    #[test]
+    fn merge_per_key() {
+        block_on(async {
+            let r1 = SPPath::from_string("robot/1");
+            let r2 = SPPath::from_string("robot/2");
+            let (mut tx, rx) = channel(10);
+            let mut buffer = KeyedStreamBuffer::new(rx, key, sum);
+            tx.send((r2.clone(), 1)).await.unwrap();
+            tx.send((r1.clone(), 2)).await.unwrap();
+            tx.send((r2.clone(), 3)).await.unwrap();
+            tx.send((r1.clone(), 4)).await.unwrap();
+            assert_eq!(buffer.next().await, Some((r2.clone(), (r2.clone(), 4))));
+            assert_eq!(buffer.next().await, Some((r1.clone(), (r1.clone(), 6))));
+
+            tx.send((r1.clone(), 1)).await.unwrap();
+            drop(tx);
+            assert_eq!(buffer.next().await, Some((r1.clone(), (r1.clone(), 1))));
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[test]
+    fn no_merge_when_false() {
+        block_on(async {
+            let r1 = SPPath::from_string("robot/1");
+            let r2 = SPPath::from_string("robot/2");
+            let xs = stream::iter(vec!(
+                (r1.clone(), 1), 
+                (r2.clone(), 2), 
+                (r1.clone(), 0), 
+                (r1.clone(), 3),
+            ));
+            let buffer = KeyedStreamBuffer::new(xs, key, |x: &mut (SPPath, usize), y: &(SPPath, usize)| {
+                y.1 != 0 && { x.1 += y.1; true }
+            });
+            let res: Vec<(SPPath, usize)> = buffer.map(|(_, x)| x).collect().await;
+            assert_eq!(res, vec!((r1.clone(), 1), (r2.clone(), 2), (r1.clone(), 3)));
+        });
+    }
+
+    #[test]
+    fn source_not_polled_after_end() {
+        block_on(async {
+            let xs = stream::unfold(0, |n| async move {
+                if n < 4 { Some(((n % 2, n), n + 1)) } else { None }
+            });
+            let buffer = KeyedStreamBuffer::new(xs, |x: &(usize, usize)| x.0, |x: &mut (usize, usize), y: &(usize, usize)| {
+                x.1 += y.1;
+                true
+            });
+            let res: Vec<(usize, usize)> = buffer.map(|(_, x)| x).collect().await;
+            assert_eq!(res, vec!((0, 2), (1, 4)));
+        });
+    }
+}
@@ -1,4 +1,6 @@
 
+mod rt;
+
 pub mod sperror;
 pub use sperror::*;
 
@@ -11,5 +13,50 @@ pub use paths::*;
 pub mod values;
 pub use values::*;
 
+pub mod stream_buffer;
+pub use stream_buffer::*;
+
+pub mod keyed_stream_buffer;
+pub use keyed_stream_buffer::*;
+
+pub mod adaptive_stream_buffer;
+pub use adaptive_stream_buffer::*;
+
+pub mod blocking_stream_buffer;
+pub use blocking_stream_buffer::*;
+
+pub mod try_stream_buffer;
+pub use try_stream_buffer::*;
+
+pub mod buffer_channel;
+pub use buffer_channel::*;
+
+pub mod multi_stream_buffer;
+pub use multi_stream_buffer::*;
+
+pub mod broadcast;
+pub use broadcast::*;
+
+pub mod merging_sink;
+pub use merging_sink::*;
+
+pub mod barrier;
+pub use barrier::*;
+
+pub mod mergeable;
+pub use mergeable::*;
+
+pub mod strategies;
+
+pub mod testing;
+
+pub mod merged;
+pub use merged::*;
+
 pub mod old_states;
-pub use old_states::*;
\ No newline at end of file
+pub use old_states::*;
+
+/// The examples in the README are tested as well.
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+pub struct ReadmeDoctests;
@@ -5,14 +5,13 @@ use sync::channel;
 
 use std::collections::hash_map::HashMap;
 
-mod stream_buffer;
-use stream_buffer::StreamBuffer;
+use spbench::StreamBuffer;
 
 
 fn main() {
     // Simple example when sending usize and adding when item is not 0
     let (tx, rx) = channel(2);
-    let mut merger = StreamBuffer::new(
+    let mut merger = StreamBuffer::from_receiver(
         rx,
         |x: &mut usize, y: &usize| {  // merging until we get a 0
             *x += y;
@@ -23,7 +22,7 @@ fn main() {
 
     // Example when struct has a hashmap that will always merge
     let (tx2, rx2) = channel(100);
-    let mut merger2 = StreamBuffer::new(
+    let mut merger2 = StreamBuffer::from_receiver(
         rx2,
         |x: &mut MyState, y: &MyState| {  // merging until we get a 0
             x.xs.extend(y.xs.iter().map(|(x, y)| (x.clone(), y.clone())));
@@ -0,0 +1,152 @@
+//! Mergeable is implemented by types that know how to merge with each other
+
+use super::*;
+use futures::stream::Stream;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A type that can merge another item of the same type into itself. Used 
+/// with StreamBuffer::from_mergeable so the merge fn does not need to be 
+/// implemented at every call site.
+pub trait Mergeable {
+    /// Merge other into self and return true, or return false if the 
+    /// items should not be merged. Then self should not be changed.
+    fn merge(&mut self, other: &Self) -> bool;
+}
+
+impl<S> StreamBuffer<S, MergeFn<S::Item>> where S: Stream, S::Item: Mergeable {
+    /// Creates a new StreamBuffer that folds the ready items using their 
+    /// Mergeable implementation.
+    /// 
+    /// # Example
+    /// ```
+    /// use futures::{executor::block_on, prelude::*};
+    /// use spbench::*;
+    ///
+    /// let xs = stream::iter(vec!(vec!(1), vec!(2, 3)));
+    /// let buffer = StreamBuffer::from_mergeable(xs);
+    ///
+    /// block_on(async move {
+    ///     let res: Vec<Vec<usize>> = buffer.collect().await;
+    ///     assert_eq!(res, vec!(vec!(1, 2, 3)));
+    /// });
+    /// ```
+    pub fn from_mergeable(stream: S) -> StreamBuffer<S, MergeFn<S::Item>> {
+        StreamBuffer::new(stream, <S::Item as Mergeable>::merge)
+    }
+}
+
+/// The variables in other are added to the state or updates the
+/// variables already in the state.
+impl Mergeable for SPState {
+    fn merge(&mut self, other: &Self) -> bool {
+        self.extend_from(other);
+        true
+    }
+}
+
+/// The latest value is kept.
+impl Mergeable for SPValue {
+    fn merge(&mut self, other: &Self) -> bool {
+        *self = other.clone();
+        true
+    }
+}
+
+/// The entries in other are added or replaces the entries in the map.
+impl<K, V> Mergeable for HashMap<K, V> where K: Eq + Hash + Clone, V: Clone {
+    fn merge(&mut self, other: &Self) -> bool {
+        self.extend(other.iter().map(|(k, v)| (k.clone(), v.clone())));
+        true
+    }
+}
+
+/// The items in other are appended.
+impl<T> Mergeable for Vec<T> where T: Clone {
+    fn merge(&mut self, other: &Self) -> bool {
+        self.extend_from_slice(other);
+        true
+    }
+}
+
+/// Integers are added, saturating at the min and max value instead of 
+/// overflowing.
+macro_rules! mergeable_integer {
+    ($($t: ty),*) => {
+        $(
+            impl Mergeable for $t {
+                fn merge(&mut self, other: &Self) -> bool {
+                    *self = self.saturating_add(*other);
+                    true
+                }
+            }
+        )*
+    };
+}
+
+/// Floats are added.
+macro_rules! mergeable_float {
+    ($($t: ty),*) => {
+        $(
+            impl Mergeable for $t {
+                fn merge(&mut self, other: &Self) -> bool {
+                    *self += *other;
+                    true
+                }
+            }
+        )*
+    };
+}
+
+mergeable_integer!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+mergeable_float!(f32, f64);
+
+
+#[cfg(test)]
+mod tests_mergeable {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+
+    #[test]
+    fn merge_states() {
+        let ab = SPPath::from_slice(&["a", "b"]);
+        let ac = SPPath::from_slice(&["a", "c"]);
+        let mut s = state!(ab => 1, ac => true);
+        assert!(s.merge(&state!(ab => 2)));
+        assert_eq!(s, state!(ab => 2, ac => true));
+        assert!(s.merge(&state!(["k", "l"] => false)));
+        assert_eq!(s, state!(["a", "b"] => 2, ["a", "c"] => true, ["k", "l"] => false));
+    }
+
+    #[test]
+    fn merge_from_mergeable() {
+        block_on(async {
+            let xs = stream::iter(vec!(1.to_spvalue(), 2.to_spvalue()));
+            let res: Vec<SPValue> = StreamBuffer::from_mergeable(xs).collect().await;
+            assert_eq!(res, vec!(2.to_spvalue()));
+
+            let mut a = HashMap::new();
+            a.insert("a", 1);
+            let mut b = HashMap::new();
+            b.insert("b", 2);
+            let xs = stream::iter(vec!(a, b));
+            let res: Vec<HashMap<&str, usize>> = StreamBuffer::from_mergeable(xs).collect().await;
+            assert_eq!(res[0].len(), 2);
+
+            let xs = stream::iter(vec!(1.5, 2.5));
+            let res: Vec<f64> = StreamBuffer::from_mergeable(xs).collect().await;
+            assert_eq!(res, vec!(4.0));
+        });
+    }
+
+    #[test]
+    fn merge_numbers_saturate() {
+        let mut x = u8::MAX - 1;
+        assert!(x.merge(&2));
+        assert_eq!(x, u8::MAX);
+        let mut y = i32::MIN + 1;
+        assert!(y.merge(&-2));
+        assert_eq!(y, i32::MIN);
+    }
+}
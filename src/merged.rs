@@ -0,0 +1,170 @@
+//! Merged wraps a value together with information about how it was merged
+
+use super::*;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task;
+use pin_project_lite::pin_project;
+use std::time::{Duration, Instant};
+
+/// A value returned by a StreamBuffer together with the number of items that 
+/// was folded into it and when the first and last of them arrived. 
+/// 
+/// With with_metadata, the items arrive when the StreamBuffer reads them from 
+/// the stream, so the time the items waited in e.g. a channel is not included
+/// in age. To include it, the producers can send items created with 
+/// Merged::new, and the StreamBuffer is created with from_merged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged<T> {
+    pub value: T,
+    pub count: usize,
+    pub first: Instant,
+    pub last: Instant,
+}
+
+impl<T> Merged<T> {
+    /// Creates a Merged for a single item that arrived now. Call this when 
+    /// the item is sent to get the time it was sent.
+    pub fn new(value: T) -> Merged<T> {
+        let now = Instant::now();
+        Merged {
+            value,
+            count: 1,
+            first: now,
+            last: now,
+        }
+    }
+
+    /// The time since the first item arrived. 
+    pub fn age(&self) -> Duration {
+        self.first.elapsed()
+    }
+
+    pub fn into_value(self) -> T {
+        self.value
+    }
+}
+
+/// Merges the values using the merger and keeps track of the metadata.
+#[derive(Debug, Clone, Copy)]
+pub struct MergeMetadata<F> {
+    merger: F,
+}
+
+impl<F> MergeMetadata<F> {
+    pub fn new(merger: F) -> MergeMetadata<F> {
+        MergeMetadata { merger }
+    }
+}
+
+impl<T, F> Merger<Merged<T>> for MergeMetadata<F> where F: Merger<T> {
+    fn merge(&mut self, buffer: &mut Merged<T>, item: &Merged<T>) -> bool {
+        self.merger.merge(&mut buffer.value, &item.value) && {
+            buffer.count += item.count;
+            buffer.last = item.last;
+            true
+        }
+    }
+}
+
+pin_project! {
+    /// A stream that wraps each item in a Merged when it is read.
+    pub struct MergedItems<S> {
+        #[pin] stream: S,
+    }
+}
+
+impl<S> MergedItems<S> {
+    pub fn new(stream: S) -> MergedItems<S> {
+        MergedItems { stream }
+    }
+}
+
+impl<S> Stream for MergedItems<S> where S: Stream {
+    type Item = Merged<S::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
+        self.project().stream.poll_next(cx).map(|x| x.map(Merged::new))
+    }
+}
+
+impl<S, F> StreamBuffer<MergedItems<S>, MergeMetadata<F>> where S: Stream, F: Merger<S::Item> {
+    /// Creates a new StreamBuffer that returns the merged items as a Merged, 
+    /// including how many items that were folded and when they arrived.
+    /// 
+    /// # Example
+    /// ```
+    /// use futures::{executor::block_on, prelude::*};
+    /// use spbench::*;
+    ///
+    /// let xs = stream::iter(vec!(1, 2, 3));
+    /// let mut buffer = StreamBuffer::with_metadata(xs, strategies::Sum);
+    ///
+    /// block_on(async move {
+    ///     let x: Merged<usize> = buffer.next().await.unwrap();
+    ///     assert_eq!(x.value, 6);
+    ///     assert_eq!(x.count, 3);
+    /// });
+    /// ```
+    pub fn with_metadata(stream: S, merger: F) -> StreamBuffer<MergedItems<S>, MergeMetadata<F>> {
+        StreamBuffer::new(MergedItems::new(stream), MergeMetadata::new(merger))
+    }
+}
+
+impl<S, T, F> StreamBuffer<S, MergeMetadata<F>> where S: Stream<Item = Merged<T>>, F: Merger<T> {
+    /// Creates a new StreamBuffer that reads items that were wrapped in a 
+    /// Merged by the producer, and folds the values using merger. Then first 
+    /// and last are when the items were sent.
+    pub fn from_merged(stream: S, merger: F) -> StreamBuffer<S, MergeMetadata<F>> {
+        StreamBuffer::new(stream, MergeMetadata::new(merger))
+    }
+}
+
+
+#[cfg(test)]
+mod tests_merged {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use futures::channel::mpsc::channel;
+
+    #[test]
+    fn count_merged() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = rx.merge_with_metadata(|x: &mut usize, y: &usize| {
+                *y != 0 && { *x += y; true }
+            });
+            tx.send(1).await.unwrap();
+            tx.send(2).await.unwrap();
+            tx.send(0).await.unwrap();
+            let x = buffer.next().await.unwrap();
+            assert_eq!((x.value, x.count), (3, 2));
+            assert!(x.first <= x.last);
+
+            // The 0 was read by the first call to next, before the sleep. The
+            // time an item waits in the channel is not included.
+            sleep(Duration::from_millis(10)).await;
+            tx.send(1).await.unwrap();
+            let y = buffer.next().await.unwrap();
+            assert_eq!((y.value, y.count), (1, 2));
+            assert!(y.last - y.first >= Duration::from_millis(10));
+            assert!(y.age() >= Duration::from_millis(10));
+        });
+    }
+
+    #[test]
+    fn timestamp_when_sent() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = StreamBuffer::from_merged(rx, strategies::Sum);
+            tx.send(Merged::new(1)).await.unwrap();
+            sleep(Duration::from_millis(10)).await;
+            tx.send(Merged::new(2)).await.unwrap();
+            let x: Merged<usize> = buffer.next().await.unwrap();
+            assert_eq!((x.value, x.count), (3, 2));
+            assert!(x.last - x.first >= Duration::from_millis(10));
+            assert!(x.age() >= Duration::from_millis(10));
+        });
+    }
+}
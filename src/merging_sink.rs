@@ -0,0 +1,164 @@
+//! The MergingSink folds items before they are sent to a sink
+
+use super::*;
+use futures::sink::Sink;
+use std::pin::Pin;
+use std::task;
+use pin_project_lite::pin_project;
+
+pin_project! {
+    /// MergingSink is a sink that folds the items using the merge fn while 
+    /// the inner sink is not ready, i.e. the same as the StreamBuffer but on
+    /// the sending side. Use it to merge before an expensive serialization or 
+    /// network write. 
+    /// 
+    /// The buffered item is sent to the inner sink when it is ready or when 
+    /// the sink is flushed. If the merge fn returns false, the sink is not 
+    /// ready for more items until the buffer has been sent.
+    /// 
+    /// # Example
+    /// ```
+    /// use futures::{executor::block_on, prelude::*};
+    /// use futures::{channel::mpsc, SinkExt};
+    /// use spbench::*;
+    ///
+    /// let (tx, mut rx) = mpsc::channel(0);
+    /// let mut sink = MergingSink::new(tx, strategies::Sum);
+    ///
+    /// block_on(async move {
+    ///     for x in 1..5 {
+    ///         sink.feed(x).await.unwrap();
+    ///     }
+    ///     assert_eq!(rx.next().await, Some(1));
+    ///     let (res, x) = futures::join!(sink.flush(), rx.next());
+    ///     assert!(res.is_ok());
+    ///     assert_eq!(x, Some(9));
+    /// });
+    /// ```
+    pub struct MergingSink<S, T, F> where F: Merger<T> {
+        #[pin] sink: S,
+        merger: F,
+        buffer: Option<T>,
+        overflow: Option<T>,
+    }
+}
+
+impl<S, T, F> MergingSink<S, T, F> where S: Sink<T>, F: Merger<T> {
+    /// Creates a new MergingSink that sends to sink and folds the items 
+    /// using merger.
+    pub fn new(sink: S, merger: F) -> MergingSink<S, T, F> {
+        MergingSink {
+            sink,
+            merger,
+            buffer: None,
+            overflow: None,
+        }
+    }
+
+    /// Returns the inner sink, the buffered item and the item that could not
+    /// be merged into it. These items have not been sent, so flush or close 
+    /// the MergingSink first to send them.
+    pub fn into_inner(self) -> (S, Option<T>, Option<T>) {
+        (self.sink, self.buffer, self.overflow)
+    }
+
+    /// Sends the buffered items to the inner sink while it is ready. Ready 
+    /// when all items has been sent.
+    fn poll_send(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Result<(), S::Error>> {
+        let mut this = self.project();
+        while this.buffer.is_some() {
+            match this.sink.as_mut().poll_ready(cx) {
+                task::Poll::Ready(Ok(())) => {
+                    let x = this.buffer.take().unwrap();
+                    *this.buffer = this.overflow.take();
+                    this.sink.as_mut().start_send(x)?;
+                }
+                task::Poll::Ready(Err(e)) => return task::Poll::Ready(Err(e)),
+                task::Poll::Pending => return task::Poll::Pending,
+            }
+        }
+        task::Poll::Ready(Ok(()))
+    }
+}
+
+impl<S, T, F> Sink<T> for MergingSink<S, T, F> where S: Sink<T>, F: Merger<T> {
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Result<(), Self::Error>> {
+        match self.as_mut().poll_send(cx) {
+            task::Poll::Pending if self.overflow.is_none() => task::Poll::Ready(Ok(())),
+            x => x,
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        match this.buffer.as_mut() {
+            None => *this.buffer = Some(item),
+            Some(b) => {
+                if !this.merger.merge(b, &item) {
+                    *this.overflow = Some(item);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Result<(), Self::Error>> {
+        futures::ready!(self.as_mut().poll_send(cx))?;
+        self.project().sink.poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Result<(), Self::Error>> {
+        futures::ready!(self.as_mut().poll_send(cx))?;
+        self.project().sink.poll_close(cx)
+    }
+}
+
+
+#[cfg(test)]
+mod tests_merging_sink {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use futures::{channel::mpsc, SinkExt};
+
+    #[test]
+    fn merge_when_not_ready() {
+        block_on(async {
+            let (tx, mut rx) = mpsc::channel(0);
+            let until_zero = strategies::MergeUntil::new(strategies::Sum, |x: &usize| *x == 0);
+            let mut sink = MergingSink::new(tx, until_zero);
+            sink.feed(1).await.unwrap();
+            sink.feed(2).await.unwrap();
+            sink.feed(3).await.unwrap();
+            sink.feed(0).await.unwrap();
+            
+            let receiver = spawn(async move {
+                let mut res = vec!();
+                while let Some(x) = rx.next().await {
+                    res.push(x);
+                }
+                res
+            });
+            sink.feed(4).await.unwrap();
+            sink.feed(5).await.unwrap();
+            sink.close().await.unwrap();
+            let res = receiver.await;
+            assert_eq!(res.iter().sum::<usize>(), 15);
+            assert_eq!(&res[..2], &[1, 5]);
+        });
+    }
+
+    #[test]
+    fn into_inner_returns_buffered_items() {
+        let until_zero = strategies::MergeUntil::new(strategies::Sum, |x: &usize| *x == 0);
+        let mut sink = MergingSink::new(Vec::new(), until_zero);
+        for x in &[1, 2, 0] {
+            Pin::new(&mut sink).start_send(*x).unwrap();
+        }
+        let (inner, buffer, overflow) = sink.into_inner();
+        assert!(inner.is_empty());
+        assert_eq!((buffer, overflow), (Some(3), Some(0)));
+    }
+}
@@ -0,0 +1,216 @@
+//! The MultiStreamBuffer folds items from multiple streams
+
+use super::*;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task;
+
+/// A value returned by the MultiStreamBuffer together with the sources that 
+/// contributed to it. A source is identified by the order it was added, 
+/// starting from 0.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MultiMerged<T> {
+    pub value: T,
+    pub sources: Vec<usize>,
+}
+
+/// MultiStreamBuffer reads from multiple streams and folds the ready items
+/// from all of them using the same merge fn.
+/// 
+/// Each source has a priority. Sources with higher priority are drained 
+/// first, and items are only merged with items from sources with the same 
+/// priority. When items from multiple priorities are ready, the items from 
+/// the highest priority are returned first.
+/// 
+/// # Example
+/// ```
+/// use futures::{channel::mpsc::channel, executor::block_on, prelude::*};
+/// use spbench::*;
+///
+/// let (mut ui_tx, ui_rx) = channel(10);
+/// let (mut ros_tx, ros_rx) = channel(10);
+/// let mut buffer = MultiStreamBuffer::new(strategies::Sum)
+///     .add_source(ui_rx, 0)
+///     .add_source(ros_rx, 10);
+///
+/// block_on(async move {
+///     ui_tx.send(1).await.unwrap();
+///     ros_tx.send(2).await.unwrap();
+///     ros_tx.send(3).await.unwrap();
+///     assert_eq!(buffer.next().await.map(|x| x.value), Some(5));
+///     assert_eq!(buffer.next().await.map(|x| x.value), Some(1));
+/// });
+/// ```
+pub struct MultiStreamBuffer<S, F> where S: Stream + Unpin, F: Merger<S::Item> {
+    sources: Vec<Source<S>>,
+    levels: Vec<Level<S::Item>>,
+    merger: F,
+}
+
+struct Source<S> {
+    stream: S,
+    done: bool,
+}
+
+/// The sources with the same priority and their buffer. If the merge fn 
+/// returns false, the item is pending until the buffer has been returned.
+struct Level<T> {
+    priority: u32,
+    sources: Vec<usize>,
+    buffer: Option<(T, Vec<usize>)>,
+    pending: Option<(T, usize)>,
+}
+
+impl<S, F> MultiStreamBuffer<S, F> where S: Stream + Unpin, F: Merger<S::Item> {
+    /// Creates a new MultiStreamBuffer without any sources.
+    pub fn new(merger: F) -> MultiStreamBuffer<S, F> {
+        MultiStreamBuffer {
+            sources: Vec::new(),
+            levels: Vec::new(),
+            merger,
+        }
+    }
+
+    /// Adds a source with a priority. Higher priority is drained first.
+    pub fn add_source(mut self, stream: S, priority: u32) -> Self {
+        let index = self.sources.len();
+        self.sources.push(Source { stream, done: false });
+        match self.levels.iter().position(|l| l.priority <= priority) {
+            Some(i) if self.levels[i].priority == priority => {
+                self.levels[i].sources.push(index);
+            }
+            pos => {
+                let level = Level {
+                    priority,
+                    sources: vec!(index),
+                    buffer: None,
+                    pending: None,
+                };
+                let pos = pos.unwrap_or(self.levels.len());
+                self.levels.insert(pos, level);
+            }
+        }
+        self
+    }
+
+    /// Polls the sources in the level until all are pending or ended, or 
+    /// until an item could not be merged.
+    fn drain(
+        sources: &mut [Source<S>], 
+        level: &mut Level<S::Item>, 
+        merger: &mut F, 
+        cx: &mut task::Context<'_>
+    ) {
+        if level.pending.is_some() {
+            return;
+        }
+        loop {
+            let mut progress = false;
+            for &i in level.sources.iter() {
+                let source = &mut sources[i];
+                if source.done {
+                    continue;
+                }
+                match Pin::new(&mut source.stream).poll_next(cx) {
+                    task::Poll::Ready(Some(x)) => {
+                        progress = true;
+                        match level.buffer.as_mut() {
+                            None => level.buffer = Some((x, vec!(i))),
+                            Some((b, xs)) => {
+                                if merger.merge(b, &x) {
+                                    if !xs.contains(&i) {
+                                        xs.push(i);
+                                    }
+                                } else {
+                                    level.pending = Some((x, i));
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    task::Poll::Ready(None) => source.done = true,
+                    task::Poll::Pending => {},
+                }
+            }
+            if !progress {
+                return;
+            }
+        }
+    }
+}
+
+impl<S, F> Stream for MultiStreamBuffer<S, F> where S: Stream + Unpin, F: Merger<S::Item> {
+    type Item = MultiMerged<S::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        for level in this.levels.iter_mut() {
+            MultiStreamBuffer::drain(&mut this.sources, level, &mut this.merger, cx);
+            if let Some((value, sources)) = level.buffer.take() {
+                level.buffer = level.pending.take().map(|(x, i)| (x, vec!(i)));
+                return task::Poll::Ready(Some(MultiMerged { value, sources }));
+            }
+        }
+        if this.sources.iter().all(|s| s.done) {
+            task::Poll::Ready(None)
+        } else {
+            task::Poll::Pending
+        }
+    }
+}
+
+impl<S, F> Unpin for MultiStreamBuffer<S, F> where S: Stream + Unpin, F: Merger<S::Item> {}
+
+
+#[cfg(test)]
+mod tests_multi_stream_buffer {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use futures::channel::mpsc::channel;
+
+    #[test]
+    fn merge_sources() {
+        block_on(async {
+            let (mut planner_tx, planner_rx) = channel(10);
+            let (mut ui_tx, ui_rx) = channel(10);
+            let (mut ros_tx, ros_rx) = channel(10);
+            let mut buffer = MultiStreamBuffer::new(strategies::Sum)
+                .add_source(planner_rx, 1)
+                .add_source(ros_rx, 10)
+                .add_source(ui_rx, 1);
+
+            planner_tx.send(1).await.unwrap();
+            ui_tx.send(2).await.unwrap();
+            ros_tx.send(10).await.unwrap();
+            ros_tx.send(20).await.unwrap();
+            assert_eq!(buffer.next().await, Some(MultiMerged { value: 30, sources: vec!(1) }));
+            assert_eq!(buffer.next().await, Some(MultiMerged { value: 3, sources: vec!(0, 2) }));
+
+            ui_tx.send(5).await.unwrap();
+            drop(planner_tx);
+            drop(ui_tx);
+            drop(ros_tx);
+            assert_eq!(buffer.next().await, Some(MultiMerged { value: 5, sources: vec!(2) }));
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[test]
+    fn no_merge_when_false() {
+        block_on(async {
+            let a = stream::iter(vec!(1, 0, 2));
+            let b = stream::iter(vec!(3));
+            let until_zero = strategies::MergeUntil::new(strategies::Sum, |x: &usize| *x == 0);
+            let res: Vec<MultiMerged<usize>> = MultiStreamBuffer::new(until_zero)
+                .add_source(a, 0)
+                .add_source(b, 0)
+                .collect()
+                .await;
+            assert_eq!(res, vec!(
+                MultiMerged { value: 4, sources: vec!(0, 1) },
+                MultiMerged { value: 2, sources: vec!(0) },
+            ));
+        });
+    }
+}
@@ -0,0 +1,97 @@
+//! The async runtime used for the timers, selected with the runtime features.
+//! Without a runtime feature there is no timer, and debounce and max_latency
+//! are not available. If both features are enabled, async-std is used.
+
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio", test))]
+use std::future::Future;
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio", test))]
+use std::pin::Pin;
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio", test))]
+use std::time::Duration;
+
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+pub(crate) type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Sleeps for the duration using the async-std timer, that runs on its own
+/// thread and can be used with any executor.
+#[cfg(feature = "runtime-async-std")]
+pub(crate) fn sleep(duration: Duration) -> Sleep {
+    Box::pin(async_std::task::sleep(duration))
+}
+
+/// Sleeps for the duration using the tokio timer. Must be polled from
+/// within a tokio runtime.
+#[cfg(all(feature = "runtime-tokio", not(feature = "runtime-async-std")))]
+pub(crate) fn sleep(duration: Duration) -> Sleep {
+    Box::pin(tokio::time::sleep(duration))
+}
+
+/// Helpers so that the tests are executed on the selected runtime. Without
+/// a runtime feature, the tests are executed on tokio from the 
+/// dev-dependencies.
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    pub(crate) use super::sleep;
+
+    #[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio")))]
+    pub fn sleep(duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        async_std::task::block_on(future)
+    }
+
+    #[cfg(not(feature = "runtime-async-std"))]
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    pub fn spawn<F>(future: F) -> async_std::task::JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static
+    {
+        async_std::task::spawn(future)
+    }
+
+    #[cfg(not(feature = "runtime-async-std"))]
+    pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static
+    {
+        JoinHandle(tokio::spawn(future))
+    }
+
+    /// The tokio JoinHandle returns an error if the task panicked.
+    #[cfg(not(feature = "runtime-async-std"))]
+    pub struct JoinHandle<T>(tokio::task::JoinHandle<T>);
+
+    #[cfg(not(feature = "runtime-async-std"))]
+    impl<T> Future for JoinHandle<T> {
+        type Output = T;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<T> {
+            Pin::new(&mut self.0).poll(cx).map(|x| x.unwrap())
+        }
+    }
+
+    /// Returns Err if the future is not ready within the duration.
+    pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, ()> {
+        use futures::future::{select, Either};
+        futures::pin_mut!(future);
+        match select(future, sleep(duration)).await {
+            Either::Left((x, _)) => Ok(x),
+            Either::Right(_) => Err(()),
+        }
+    }
+}
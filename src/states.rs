@@ -394,6 +394,14 @@ impl SPState {
         let p = other_state.extract();
         self.add_state_variables(p);
     }
+
+    /// Same as extend, but clones the variables from the other state instead
+    /// of taking it.
+    pub fn extend_from(&mut self, other_state: &SPState) {
+        for (path, i) in other_state.index.iter() {
+            self.add_state_variable(path.clone(), other_state.values[*i].clone());
+        }
+    }
 }
 
 impl fmt::Display for SPState {
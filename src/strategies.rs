@@ -0,0 +1,177 @@
+//! Ready made merge strategies that can be used with the StreamBuffer
+
+use super::*;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::AddAssign;
+
+/// Keeps the latest item.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LatestWins;
+
+impl<T> Merger<T> for LatestWins where T: Clone {
+    fn merge(&mut self, buffer: &mut T, item: &T) -> bool {
+        *buffer = item.clone();
+        true
+    }
+}
+
+/// Keeps the first item and drops the rest.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstWins;
+
+impl<T> Merger<T> for FirstWins {
+    fn merge(&mut self, _buffer: &mut T, _item: &T) -> bool {
+        true
+    }
+}
+
+/// Adds the items using +=. For integers, this panics on overflow in debug 
+/// builds and wraps in release builds. Use Mergeable::merge, that saturates, 
+/// if the sum can overflow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sum;
+
+impl<T> Merger<T> for Sum where T: for<'a> AddAssign<&'a T> {
+    fn merge(&mut self, buffer: &mut T, item: &T) -> bool {
+        *buffer += item;
+        true
+    }
+}
+
+/// Extends the map with the entries of the item. Entries with the 
+/// same key are replaced.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExtendMap;
+
+impl<K, V> Merger<HashMap<K, V>> for ExtendMap where K: Eq + Hash + Clone, V: Clone {
+    fn merge(&mut self, buffer: &mut HashMap<K, V>, item: &HashMap<K, V>) -> bool {
+        buffer.extend(item.iter().map(|(k, v)| (k.clone(), v.clone())));
+        true
+    }
+}
+
+/// Appends the items of the item to the vec.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AppendVec;
+
+impl<T> Merger<Vec<T>> for AppendVec where T: Clone {
+    fn merge(&mut self, buffer: &mut Vec<T>, item: &Vec<T>) -> bool {
+        buffer.extend_from_slice(item);
+        true
+    }
+}
+
+/// Merges the items using the merger until the predicate is true for an 
+/// item. That item will not be merged and will start a new buffer.
+/// 
+/// # Example
+/// ```
+/// use futures::{executor::block_on, prelude::*};
+/// use spbench::*;
+/// use spbench::strategies::*;
+///
+/// let xs = stream::iter(vec!(1, 2, 0, 3, 4));
+/// let buffer = StreamBuffer::new(xs, MergeUntil::new(Sum, |x: &usize| *x == 0));
+///
+/// block_on(async move {
+///     let res: Vec<usize> = buffer.collect().await;
+///     assert_eq!(res, vec!(3, 7));
+/// });
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct MergeUntil<M, P> {
+    merger: M,
+    predicate: P,
+}
+
+impl<M, P> MergeUntil<M, P> {
+    pub fn new(merger: M, predicate: P) -> MergeUntil<M, P> {
+        MergeUntil { merger, predicate }
+    }
+}
+
+impl<T, M, P> Merger<T> for MergeUntil<M, P> where M: Merger<T>, P: FnMut(&T) -> bool {
+    fn merge(&mut self, buffer: &mut T, item: &T) -> bool {
+        !(self.predicate)(item) && self.merger.merge(buffer, item)
+    }
+}
+
+/// Chains two strategies. The first strategy is tried first and if it 
+/// does not merge the item, the second strategy is tried.
+#[derive(Debug, Clone, Copy)]
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Chain<A, B> {
+    pub fn new(first: A, second: B) -> Chain<A, B> {
+        Chain { first, second }
+    }
+
+    /// Adds another strategy to the end of the chain.
+    pub fn chain<C>(self, next: C) -> Chain<Chain<A, B>, C> {
+        Chain::new(self, next)
+    }
+}
+
+impl<T, A, B> Merger<T> for Chain<A, B> where A: Merger<T>, B: Merger<T> {
+    fn merge(&mut self, buffer: &mut T, item: &T) -> bool {
+        self.first.merge(buffer, item) || self.second.merge(buffer, item)
+    }
+}
+
+
+#[cfg(test)]
+mod tests_strategies {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+
+    fn fold<T, M>(xs: Vec<T>, merger: M) -> Vec<T> where M: Merger<T> {
+        block_on(async {
+            StreamBuffer::new(stream::iter(xs), merger).collect().await
+        })
+    }
+
+    #[test]
+    fn simple_strategies() {
+        assert_eq!(fold(vec!(1, 2, 3), LatestWins), vec!(3));
+        assert_eq!(fold(vec!(1, 2, 3), FirstWins), vec!(1));
+        assert_eq!(fold(vec!(1, 2, 3), Sum), vec!(6));
+        assert_eq!(fold(vec!(vec!(1), vec!(2, 3)), AppendVec), vec!(vec!(1, 2, 3)));
+
+        let mut a = HashMap::new();
+        a.insert("a", 1);
+        a.insert("b", 1);
+        let mut b = HashMap::new();
+        b.insert("b", 2);
+        let mut res = HashMap::new();
+        res.insert("a", 1);
+        res.insert("b", 2);
+        assert_eq!(fold(vec!(a, b), ExtendMap), vec!(res));
+    }
+
+    #[test]
+    fn merge_until() {
+        let until_zero = MergeUntil::new(Sum, |x: &usize| *x == 0);
+        assert_eq!(fold(vec!(1, 2, 0, 3, 0, 0, 4), until_zero), vec!(3, 3, 0, 4));
+    }
+
+    #[test]
+    fn chain() {
+        // small items are added, medium items replace and large items are not merged
+        let strategy = Chain::new(
+            MergeUntil::new(Sum, |x: &usize| *x > 10), 
+            MergeUntil::new(LatestWins, |x: &usize| *x > 100),
+        );
+        assert_eq!(fold(vec!(1, 2, 20, 3, 200, 1), strategy), vec!(23, 201));
+
+        let strategy = Chain::new(
+            MergeUntil::new(Sum, |x: &usize| *x > 10), 
+            MergeUntil::new(LatestWins, |x: &usize| *x > 100),
+        ).chain(FirstWins);
+        assert_eq!(fold(vec!(1, 2, 20, 3, 200, 1), strategy), vec!(24));
+    }
+}
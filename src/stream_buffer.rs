@@ -1,21 +1,66 @@
-use async_std::*;
-use sync::{Receiver};
-use stream::Stream;
-use pin::Pin;
+use super::*;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task;
 use pin_project_lite::pin_project;
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use futures::task::AtomicWaker;
+#[cfg(feature = "runtime-async-std")]
+use async_std::sync::Receiver;
+
+/// The merge fn used by the StreamBuffer. Merge the item into the buffer and 
+/// return true, or return false if the item should not be merged. Then the 
+/// buffer will be returned and the item will be the new buffer.
+/// 
+/// Merger is implemented for all FnMut(&mut T, &T) -> bool, so a closure can
+/// be used as a merger.
+pub trait Merger<T> {
+    fn merge(&mut self, buffer: &mut T, item: &T) -> bool;
+}
+
+/// A merge fn pointer, used when the merger is a plain fn.
+pub type MergeFn<T> = fn(&mut T, &T) -> bool;
+
+impl<T, F> Merger<T> for F where F: FnMut(&mut T, &T) -> bool {
+    fn merge(&mut self, buffer: &mut T, item: &T) -> bool {
+        self(buffer, item)
+    }
+}
 
 pin_project! {
-    /// StreamBuffer is a stream buffer that will fold messages waiting in 
-    /// the incoming channel until the channel is empty or if the merge fn
+    /// StreamBuffer is a stream buffer that will fold items waiting in 
+    /// the incoming stream until the stream is pending or if the merge fn
     /// returns false. This will happen for each call to next. 
     /// 
     /// Implement a folding function that merges the 
     /// items and return true if an item was merged with previous. 
     /// If you do not want to merge two items, just return false
+    ///
+    /// Any stream can be buffered, e.g. channels, tcp readers or intervals.
+    /// Use from_receiver when reading from an async-std channel to skip polling the 
+    /// channel when it is empty.
+    ///
+    /// The buffer can also wait for more items before it is returned, by 
+    /// using debounce and max_latency. Then items arriving during the time
+    /// window are also folded into the buffer.
+    ///
+    /// Use max_items or max_weight to limit how much is folded into the 
+    /// buffer, to keep the latency down when the producer is fast.
+    ///
+    /// When the stream ends, the buffer is always returned before the 
+    /// StreamBuffer ends. Use a ShutdownHandle to close the stream and end 
+    /// the StreamBuffer when the items already sent have been folded. The 
+    /// stream must implement Close, as the futures channels and the 
+    /// buffer_channel do.
     /// 
     /// # Example
     /// ```
-    /// let (tx, rx) = channel(2);
+    /// use futures::{channel::mpsc::channel, executor::block_on, prelude::*};
+    /// use spbench::StreamBuffer;
+    ///
+    /// let (mut tx, rx) = channel(10);
     /// let mut merger = StreamBuffer::new(
     ///     rx,
     ///     |x: &mut usize, y: &usize| {  // merging until we get a 0
@@ -23,54 +68,811 @@ pin_project! {
     ///         *x != 0
     ///     }
     /// );
-    /// 
-    /// task::block_on(async move {
-    /// while let Some(x) = merger.next().await {
-    ///    std::println!("GOT: {:?}", x);
-    /// }
+    ///
+    /// block_on(async move {
+    ///     tx.send(1).await.unwrap();
+    ///     tx.send(2).await.unwrap();
+    ///     tx.send(3).await.unwrap();
+    ///     drop(tx);
+    ///     assert_eq!(merger.next().await, Some(6));
+    ///     assert_eq!(merger.next().await, None);
     /// });
-    /// 
     /// ```
     ///
-    pub struct StreamBuffer<T, F> where F: FnMut(&mut T, &T) -> bool {
-        #[pin] rx: Receiver<T>,
+    pub struct StreamBuffer<S, F> where S: Stream, F: Merger<S::Item> {
+        #[pin] stream: S,
         merger: F, 
-        buffer: Option<T>,
+        buffer: Option<S::Item>,
+        is_empty: fn(&S) -> bool,
+        window: Window,
+        limits: Limits<S::Item>,
+        urgent: Option<Urgent<S::Item>>,
+        ready: Option<S::Item>,
+        shutdown: Option<Arc<Shutdown>>,
+        close: Option<fn(Pin<&mut S>)>,
+        done: bool,
     }
 }
 
-impl<T, F> StreamBuffer<T, F> where F: FnMut(&mut T, &T) -> bool {
-    pub fn new(rx: Receiver<T>, merger: F) -> StreamBuffer<T, F> {
+impl<S, F> StreamBuffer<S, F> where S: Stream, F: Merger<S::Item> {
+    /// Creates a new StreamBuffer that reads from the stream and folds the 
+    /// ready items using merger.
+    pub fn new(stream: S, merger: F) -> StreamBuffer<S, F> {
+        StreamBuffer::with_is_empty(stream, merger, |_| false)
+    }
+
+    /// Creates a new StreamBuffer that will not poll the stream when is_empty 
+    /// is true and there is a buffered item to return.
+    pub(crate) fn with_is_empty(stream: S, merger: F, is_empty: fn(&S) -> bool) -> StreamBuffer<S, F> {
         StreamBuffer{
-            rx, 
+            stream, 
             merger,
             buffer: None,
+            is_empty,
+            window: Window::default(),
+            limits: Limits::default(),
+            urgent: None,
+            ready: None,
+            shutdown: None,
+            close: None,
+            done: false,
         }
     }
+
+    pub(crate) fn merger(&self) -> &F {
+        &self.merger
+    }
+
+    /// Returns a handle that can be used to close the stream and end the 
+    /// StreamBuffer.
+    pub fn shutdown_handle(&mut self) -> ShutdownHandle where S: Close {
+        self.close = Some(<S as Close>::close);
+        let shutdown = self.shutdown.get_or_insert_with(|| Arc::new(Shutdown::default()));
+        ShutdownHandle { shutdown: shutdown.clone() }
+    }
+
+    /// Keeps folding items into the buffer until no item has arrived 
+    /// for the duration.
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    pub fn debounce(mut self, duration: Duration) -> Self {
+        self.window.debounce = Some(duration);
+        self
+    }
+
+    /// Keeps folding items into the buffer until the duration has passed 
+    /// since the first item in the buffer arrived. Use this for at most 
+    /// one item per duration.
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    pub fn max_latency(mut self, duration: Duration) -> Self {
+        self.window.max_latency = Some(duration);
+        self
+    }
+
+    /// Uses the clock for debounce and max_latency instead of the wall 
+    /// clock. Use this with a testing::ManualClock in tests.
+    /// 
+    /// # Example
+    /// ```
+    /// use spbench::*;
+    /// use spbench::testing::*;
+    /// use std::task::Poll;
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let (script, source) = scripted();
+    /// let mut buffer = StreamBuffer::new(source, strategies::Sum)
+    ///     .debounce(Duration::from_millis(10))
+    ///     .with_clock(clock.clone());
+    ///
+    /// script.push_all(vec!(1, 2, 3));
+    /// assert_eq!(poll_once(&mut buffer), Poll::Pending);
+    /// clock.advance(Duration::from_millis(5));
+    /// script.push(4);
+    /// assert_eq!(poll_once(&mut buffer), Poll::Pending);
+    /// clock.advance(Duration::from_millis(10));
+    /// assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(10)));
+    /// ```
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    pub fn with_clock<C>(mut self, clock: C) -> Self 
+    where 
+        C: testing::Clock + 'static 
+    {
+        self.window.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the buffer when max items has been folded into it, 
+    /// even if more items are ready.
+    pub fn max_items(mut self, max: usize) -> Self {
+        self.limits.max_items = Some(max);
+        self
+    }
+
+    /// Returns the buffer when the total weight of the items folded into 
+    /// it has reached max, even if more items are ready.
+    pub fn max_weight<W>(mut self, weight: W, max: usize) -> Self 
+    where 
+        W: FnMut(&S::Item) -> usize + Send + 'static 
+    {
+        self.limits.max_weight = Some((Box::new(weight), max));
+        self
+    }
+
+    /// Items where the predicate is true are never folded or delayed. The 
+    /// buffer is returned first, and then the urgent item.
+    pub fn urgent<P>(mut self, predicate: P) -> Self 
+    where 
+        P: FnMut(&S::Item) -> bool + Send + 'static 
+    {
+        self.urgent = Some(Box::new(predicate));
+        self
+    }
 }
 
-impl<T, F> Stream for StreamBuffer<T, F>  where T: Clone, F: FnMut(&mut T, &T) -> bool {
-    type Item = T;
+#[cfg(feature = "runtime-async-std")]
+impl<T, F> StreamBuffer<Receiver<T>, F> where F: Merger<T> {
+    /// Creates a new StreamBuffer that reads from a channel. The channel is 
+    /// not polled when it is empty and there is a buffered item to return.
+    pub fn from_receiver(rx: Receiver<T>, merger: F) -> StreamBuffer<Receiver<T>, F> {
+        StreamBuffer::with_is_empty(rx, merger, Receiver::is_empty)
+    }
+}
 
+impl<S, F> Stream for StreamBuffer<S, F> 
+where 
+    S: Stream, 
+    F: Merger<S::Item>
+{
+    type Item = S::Item;
+
+    /// Polls the stream until it is pending or ended and folds the items
+    /// into the buffer. The stream always gets the context, so the task will
+    /// be woken when new items arrives even if the buffer was returned.
     fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
-        let this = self.project();
+        let mut this = self.project();
+        let stopping = match this.shutdown.as_ref() {
+            Some(s) => {
+                s.waker.register(cx.waker());
+                s.flag.load(Ordering::SeqCst)
+            }
+            None => false,
+        };
+        if stopping {
+            if let Some(close) = this.close.take() {
+                close(this.stream.as_mut());
+            }
+        }
         loop {
-            if this.rx.is_empty() && this.buffer.is_none() {
-                return this.rx.poll_next(cx);
-            } else if this.rx.is_empty() && this.buffer.is_some() {
-                let temp = this.buffer.clone();
-                *this.buffer = None;
-                return task::Poll::Ready(temp);
+            if let Some(x) = this.ready.take() {
+                return task::Poll::Ready(Some(x));
+            }
+            if *this.done || (this.buffer.is_some() && (this.limits.is_reached() || this.window.is_elapsed())) {
+                return task::Poll::Ready(this.buffer.take());
+            }
+            let next = if this.buffer.is_some() && !this.window.is_timed() && (this.is_empty)(&this.stream) {
+                task::Poll::Pending
             } else {
-                let x = task::block_on(this.rx.recv()).unwrap();
-                if this.buffer.is_none() {
-                    *this.buffer = Some(x);
-                } else if !(this.merger)(this.buffer.as_mut().unwrap(), &x) {
-                    let temp = this.buffer.clone();
-                    *this.buffer = Some(x);
-                    return task::Poll::Ready(temp);
+                this.stream.as_mut().poll_next(cx)
+            };
+            match next {
+                task::Poll::Ready(Some(x)) => {
+                    if this.urgent.as_mut().map(|u| u(&x)).unwrap_or(false) {
+                        if this.buffer.is_none() {
+                            return task::Poll::Ready(Some(x));
+                        }
+                        *this.ready = Some(x);
+                        return task::Poll::Ready(this.buffer.take());
+                    } else if this.buffer.is_none() {
+                        this.window.start();
+                        this.limits.start(&x);
+                        *this.buffer = Some(x);
+                    } else if this.merger.merge(this.buffer.as_mut().unwrap(), &x) {
+                        this.window.extend();
+                        this.limits.extend(&x);
+                    } else {
+                        this.window.start();
+                        this.limits.start(&x);
+                        return task::Poll::Ready(this.buffer.replace(x));
+                    }
+                }
+                task::Poll::Ready(None) => {
+                    *this.done = true;
+                    return task::Poll::Ready(this.buffer.take());
+                }
+                task::Poll::Pending if stopping => {
+                    *this.done = true;
+                    return task::Poll::Ready(this.buffer.take());
+                }
+                task::Poll::Pending if this.buffer.is_none() => {
+                    return task::Poll::Pending;
+                }
+                task::Poll::Pending => {
+                    return this.window.poll_elapsed(cx).map(|_| this.buffer.take());
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Default)]
+struct Shutdown {
+    flag: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// A handle used to end a StreamBuffer before the stream has ended. 
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<Shutdown>,
+}
+
+impl ShutdownHandle {
+    /// Ends the StreamBuffer. The stream is closed, so that no more items 
+    /// can be sent, and the items already in the stream are folded into the 
+    /// buffer and returned. Then the StreamBuffer ends.
+    pub fn shutdown(&self) {
+        self.shutdown.flag.store(true, Ordering::SeqCst);
+        self.shutdown.waker.wake();
+    }
+}
+
+/// A stream that can be closed, used by the ShutdownHandle. When closed, no 
+/// more items can be sent to the stream, but the items already sent can be 
+/// read before the stream ends.
+pub trait Close {
+    fn close(self: Pin<&mut Self>);
+}
+
+impl<T> Close for futures::channel::mpsc::Receiver<T> {
+    fn close(self: Pin<&mut Self>) {
+        self.get_mut().close();
+    }
+}
+
+impl<T> Close for futures::channel::mpsc::UnboundedReceiver<T> {
+    fn close(self: Pin<&mut Self>) {
+        self.get_mut().close();
+    }
+}
+
+type Urgent<T> = Box<dyn FnMut(&T) -> bool + Send>;
+
+type Weight<T> = Box<dyn FnMut(&T) -> usize + Send>;
+
+/// The limits of how many items, or how much weight, that can be folded
+/// into the buffer.
+struct Limits<T> {
+    max_items: Option<usize>,
+    max_weight: Option<(Weight<T>, usize)>,
+    items: usize,
+    weight: usize,
+}
+
+impl<T> Default for Limits<T> {
+    fn default() -> Self {
+        Limits {
+            max_items: None,
+            max_weight: None,
+            items: 0,
+            weight: 0,
+        }
+    }
+}
+
+impl<T> Limits<T> {
+    /// A new buffer is started with the item
+    fn start(&mut self, item: &T) {
+        self.items = 0;
+        self.weight = 0;
+        self.extend(item);
+    }
+
+    /// The item was merged into the buffer
+    fn extend(&mut self, item: &T) {
+        self.items += 1;
+        if let Some((w, _)) = self.max_weight.as_mut() {
+            self.weight += w(item);
+        }
+    }
+
+    fn is_reached(&self) -> bool {
+        self.max_items.map(|m| self.items >= m).unwrap_or(false)
+            || self.max_weight.as_ref().map(|(_, m)| self.weight >= *m).unwrap_or(false)
+    }
+}
+
+/// The time window used by the timed StreamBuffer. Keeps track of when the
+/// first and the last item in the buffer arrived.
+struct Window {
+    debounce: Option<Duration>,
+    max_latency: Option<Duration>,
+    first: Option<Instant>,
+    last: Option<Instant>,
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    sleep: Option<(Instant, rt::Sleep)>,
+    clock: Arc<dyn testing::Clock>,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Window {
+            debounce: None,
+            max_latency: None,
+            first: None,
+            last: None,
+            #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+            sleep: None,
+            clock: Arc::new(testing::SystemClock),
+        }
+    }
+}
+
+impl Window {
+    fn is_timed(&self) -> bool {
+        self.debounce.is_some() || self.max_latency.is_some()
+    }
+
+    /// A new buffer is started
+    fn start(&mut self) {
+        if self.is_timed() {
+            let now = self.clock.now();
+            self.first = Some(now);
+            self.last = Some(now);
+        }
+    }
+
+    /// An item was merged into the buffer
+    fn extend(&mut self) {
+        if self.is_timed() {
+            self.last = Some(self.clock.now());
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        let debounce = self.debounce.and_then(|d| self.last.map(|l| l + d));
+        let max_latency = self.max_latency.and_then(|d| self.first.map(|f| f + d));
+        match (debounce, max_latency) {
+            (Some(d), Some(m)) => Some(std::cmp::min(d, m)),
+            (d, m) => d.or(m),
+        }
+    }
+
+    /// True when the deadline has passed, also if items are still arriving.
+    fn is_elapsed(&self) -> bool {
+        self.deadline().map(|d| self.clock.now() >= d).unwrap_or(false)
+    }
+
+    /// Ready when the buffer should be returned. Registers the waker 
+    /// with a timer if the deadline has not yet passed.
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    fn poll_elapsed(&mut self, cx: &mut task::Context<'_>) -> task::Poll<()> {
+        let deadline = match self.deadline() {
+            Some(deadline) => deadline,
+            None => return task::Poll::Ready(()),
+        };
+        let now = self.clock.now();
+        if now >= deadline {
+            self.sleep = None;
+            return task::Poll::Ready(());
+        }
+        match &self.sleep {
+            Some((d, _)) if *d == deadline => {},
+            _ => self.sleep = Some((deadline, self.clock.sleep(deadline - now))),
+        }
+        let res = self.sleep.as_mut().unwrap().1.as_mut().poll(cx);
+        if res.is_ready() {
+            self.sleep = None;
+        }
+        res
+    }
+
+    /// Without a runtime the window can not be timed, so the buffer is 
+    /// always returned.
+    #[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio")))]
+    fn poll_elapsed(&mut self, _cx: &mut task::Context<'_>) -> task::Poll<()> {
+        task::Poll::Ready(())
+    }
+}
+
+
+/// Extension trait for buffering any stream in a combinator chain.
+///
+/// # Example
+/// ```
+/// use futures::{executor::block_on, prelude::*};
+/// use spbench::StreamBufferExt;
+///
+/// block_on(async {
+///     let xs: Vec<usize> = stream::iter(vec!(1, 2, 3))
+///         .merge_buffered(|acc, x| {
+///             *acc += x;
+///             true
+///         })
+///         .collect()
+///         .await;
+///     assert_eq!(xs, vec!(6));
+/// });
+/// ```
+pub trait StreamBufferExt: Stream {
+    /// Buffers the stream and folds the ready items using the closure f.
+    fn merge_buffered<F>(self, f: F) -> StreamBuffer<Self, F> 
+    where 
+        Self: Sized, 
+        F: FnMut(&mut Self::Item, &Self::Item) -> bool 
+    {
+        StreamBuffer::new(self, f)
+    }
+
+    /// Buffers the stream and only keeps the latest of the ready items.
+    fn merge_latest(self) -> StreamBuffer<Self, strategies::LatestWins> 
+    where 
+        Self: Sized, 
+        Self::Item: Clone 
+    {
+        StreamBuffer::new(self, strategies::LatestWins)
+    }
+
+    /// Buffers the stream and folds the ready items using the merger.
+    fn merge_with<M>(self, merger: M) -> StreamBuffer<Self, M> 
+    where 
+        Self: Sized, 
+        M: Merger<Self::Item> 
+    {
+        StreamBuffer::new(self, merger)
+    }
+
+    /// Buffers the stream and folds the ready items using the closure f. 
+    /// Returns the merged items as a Merged, including metadata about the 
+    /// merge.
+    fn merge_with_metadata<F>(self, f: F) -> StreamBuffer<MergedItems<Self>, MergeMetadata<F>> 
+    where 
+        Self: Sized, 
+        F: FnMut(&mut Self::Item, &Self::Item) -> bool 
+    {
+        StreamBuffer::with_metadata(self, f)
+    }
+
+    /// Buffers a stream of Item and folds the data between the barriers 
+    /// using the closure f.
+    fn merge_with_barriers<T, F>(self, f: F) -> BarrierStreamBuffer<Self, BarrierMerger<F>> 
+    where 
+        Self: Stream<Item = Item<T>> + Sized, 
+        F: FnMut(&mut T, &T) -> bool 
+    {
+        BarrierStreamBuffer::new(self, f)
+    }
+
+    /// Returns the items one by one while the consumer keeps up, and folds 
+    /// the backlog using the closure f when the consumer falls behind.
+    fn merge_when_lagging<F>(self, f: F) -> AdaptiveStreamBuffer<Self, F> 
+    where 
+        Self: Sized, 
+        F: FnMut(&mut Self::Item, &Self::Item) -> bool 
+    {
+        AdaptiveStreamBuffer::new(self, f)
+    }
+
+    /// Buffers the stream and folds the ready items with the same key.
+    fn merge_keyed<K, KF, F>(self, key: KF, f: F) -> KeyedStreamBuffer<Self, K, KF, F> 
+    where 
+        Self: Sized, 
+        K: Eq + std::hash::Hash + Clone,
+        KF: FnMut(&Self::Item) -> K,
+        F: FnMut(&mut Self::Item, &Self::Item) -> bool 
+    {
+        KeyedStreamBuffer::new(self, key, f)
+    }
+}
+
+impl<S> StreamBufferExt for S where S: Stream {}
+
+#[cfg(test)]
+mod tests_stream_buffer {
+    use super::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+    use futures::channel::mpsc::channel;
+    use std::time::Duration;
+
+    fn sum_until_zero(x: &mut usize, y: &usize) -> bool {
+        *x += y;
+        *x != 0
+    }
+
+    #[test]
+    fn merge_waiting_items() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero);
+            for x in 1..5 {
+                tx.send(x).await.unwrap();
+            }
+            drop(tx);
+            assert_eq!(buffer.next().await, Some(10));
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[test]
+    fn no_merge_when_false() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let buffer = StreamBuffer::new(rx, |_: &mut usize, _: &usize| false);
+            for x in 1..5 {
+                tx.send(x).await.unwrap();
+            }
+            drop(tx);
+            let res: Vec<usize> = buffer.collect().await;
+            assert_eq!(res, vec!(1, 2, 3, 4));
+        });
+    }
+
+    #[test]
+    fn pending_when_empty() {
+        block_on(async {
+            let (mut tx, rx) = channel::<usize>(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero);
+            let res = timeout(Duration::from_millis(10), buffer.next()).await;
+            assert!(res.is_err());
+            tx.send(1).await.unwrap();
+            assert_eq!(buffer.next().await, Some(1));
+        });
+    }
+
+    #[test]
+    fn woken_by_sender() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero);
+            spawn(async move {
+                sleep(Duration::from_millis(10)).await;
+                tx.send(1).await.unwrap();
+            });
+            assert_eq!(buffer.next().await, Some(1));
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    #[test]
+    fn merge_from_receiver() {
+        block_on(async {
+            let (tx, rx) = async_std::sync::channel(10);
+            let mut buffer = StreamBuffer::from_receiver(rx, sum_until_zero);
+            for x in 1..5 {
+                tx.send(x).await;
+            }
+            assert_eq!(buffer.next().await, Some(10));
+            tx.send(5).await;
+            drop(tx);
+            assert_eq!(buffer.next().await, Some(5));
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[test]
+    fn merge_any_stream() {
+        block_on(async {
+            let xs = stream::iter(vec!(1, 2, 0, 3, 4));
+            let buffer = StreamBuffer::new(xs, |x: &mut usize, y: &usize| {
+                if *y == 0 { 
+                    false
+                } else {
+                    *x += y;
+                    true
+                }
+            });
+            let res: Vec<usize> = buffer.collect().await;
+            assert_eq!(res, vec!(3, 7));
+        });
+    }
+
+    #[test]
+    fn merge_with_ext() {
+        block_on(async {
+            let res: Vec<usize> = stream::iter(vec!(1, 2, 3))
+                .merge_latest()
+                .collect()
+                .await;
+            assert_eq!(res, vec!(3));
+
+            struct Max;
+            impl Merger<usize> for Max {
+                fn merge(&mut self, buffer: &mut usize, item: &usize) -> bool {
+                    *buffer = std::cmp::max(*buffer, *item);
+                    true
+                }
+            }
+            let res: Vec<usize> = stream::iter(vec!(1, 5, 3))
+                .merge_with(Max)
+                .collect()
+                .await;
+            assert_eq!(res, vec!(5));
+
+            let (mut tx, rx) = channel(10);
+            let mut buffer = rx.merge_buffered(|acc, x| {
+                *acc + x <= 6 && { *acc += x; true }
+            });
+            for x in 1..5 {
+                tx.send(x).await.unwrap();
+            }
+            drop(tx);
+            assert_eq!(buffer.next().await, Some(6));
+            assert_eq!(buffer.next().await, Some(4));
+        });
+    }
+
+    #[test]
+    fn merge_without_clone() {
+        #[derive(Debug, PartialEq)]
+        struct NoClone(Vec<usize>);
+        block_on(async {
+            let xs = stream::iter(vec!(NoClone(vec!(1)), NoClone(vec!(2))));
+            let res: Vec<NoClone> = xs
+                .merge_buffered(|acc, x| {
+                    acc.0.extend(x.0.iter());
+                    true
+                })
+                .collect()
+                .await;
+            assert_eq!(res, vec!(NoClone(vec!(1, 2))));
+        });
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn debounce() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero)
+                .debounce(Duration::from_millis(50));
+            tx.send(1).await.unwrap();
+            spawn(async move {
+                sleep(Duration::from_millis(5)).await;
+                tx.send(2).await.unwrap();
+                sleep(Duration::from_millis(5)).await;
+                tx.send(3).await.unwrap();
+            });
+            assert_eq!(buffer.next().await, Some(6));
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn max_latency() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let buffer = StreamBuffer::new(rx, sum_until_zero)
+                .max_latency(Duration::from_millis(30));
+            spawn(async move {
+                for _ in 0..20 {
+                    tx.send(1).await.unwrap();
+                    sleep(Duration::from_millis(5)).await;
+                }
+            });
+            let res: Vec<usize> = buffer.collect().await;
+            assert_eq!(res.iter().sum::<usize>(), 20);
+            assert!(res.len() < 10);
+        });
+    }
+
+    #[test]
+    fn max_items() {
+        block_on(async {
+            let xs = stream::iter(vec!(1, 2, 3, 4, 5));
+            let buffer = StreamBuffer::new(xs, sum_until_zero).max_items(2);
+            let res: Vec<usize> = buffer.collect().await;
+            assert_eq!(res, vec!(3, 7, 5));
+        });
+    }
+
+    #[test]
+    fn max_weight() {
+        block_on(async {
+            let xs = stream::iter(vec!(1, 2, 3, 4, 1));
+            let buffer = StreamBuffer::new(xs, sum_until_zero)
+                .max_weight(|x: &usize| *x, 5);
+            let res: Vec<usize> = buffer.collect().await;
+            assert_eq!(res, vec!(6, 5));
+        });
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn urgent_items() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero)
+                .debounce(Duration::from_secs(10))
+                .urgent(|x| *x >= 100);
+            for x in &[1, 2, 100, 3, 4] {
+                tx.send(*x).await.unwrap();
+            }
+            let res = timeout(Duration::from_secs(1), buffer.next()).await;
+            assert_eq!(res, Ok(Some(3)));
+            assert_eq!(buffer.next().await, Some(100));
+            tx.send(200).await.unwrap();
+            drop(tx);
+            assert_eq!(buffer.next().await, Some(7));
+            assert_eq!(buffer.next().await, Some(200));
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn flush_when_senders_dropped() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero)
+                .debounce(Duration::from_secs(10));
+            tx.send(1).await.unwrap();
+            tx.send(2).await.unwrap();
+            drop(tx);
+            let res = timeout(Duration::from_secs(1), buffer.next()).await;
+            assert_eq!(res, Ok(Some(3)));
+            assert_eq!(buffer.next().await, None);
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[test]
+    fn shutdown() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero);
+            let handle = buffer.shutdown_handle();
+            tx.send(1).await.unwrap();
+            tx.send(2).await.unwrap();
+            handle.shutdown();
+            assert_eq!(buffer.next().await, Some(3));
+            assert!(tx.send(3).await.is_err());
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[test]
+    fn shutdown_drains_all_items() {
+        block_on(async {
+            let (mut tx, rx) = channel(200);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero);
+            for x in 1..=150 {
+                tx.send(x).await.unwrap();
+            }
+            buffer.shutdown_handle().shutdown();
+            assert_eq!(buffer.next().await, Some((1..=150).sum()));
+            assert!(tx.try_send(151).is_err());
+            assert_eq!(buffer.next().await, None);
+        });
+    }
+
+    #[test]
+    fn shutdown_when_waiting() {
+        block_on(async {
+            let (tx, rx) = channel::<usize>(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero);
+            let handle = buffer.shutdown_handle();
+            let res = spawn(async move {
+                buffer.next().await
+            });
+            sleep(Duration::from_millis(10)).await;
+            handle.shutdown();
+            assert_eq!(res.await, None);
+            drop(tx);
+        });
+    }
+
+    #[test]
+    fn items_sent_after_poll() {
+        block_on(async {
+            let (mut tx, rx) = channel(10);
+            let mut buffer = StreamBuffer::new(rx, sum_until_zero);
+            tx.send(1).await.unwrap();
+            tx.send(2).await.unwrap();
+            assert_eq!(buffer.next().await, Some(3));
+            tx.send(5).await.unwrap();
+            assert_eq!(buffer.next().await, Some(5));
+        });
+    }
+}
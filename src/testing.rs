@@ -0,0 +1,480 @@
+//! Helpers for testing how items are merged, without depending on the
+//! scheduler or on the wall clock
+
+use futures::stream::Stream;
+use std::collections::VecDeque;
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task;
+use std::time::{Duration, Instant};
+
+/// The clock used by the timed StreamBuffer for debounce and max_latency.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// A future that is ready when the duration has passed. Only available 
+    /// with a runtime feature.
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// The wall clock, using the timer of the selected runtime.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        crate::rt::sleep(duration)
+    }
+}
+
+/// A clock that only moves when advance is called. Clones share the same
+/// time.
+#[derive(Clone)]
+pub struct ManualClock {
+    inner: Arc<Mutex<ManualTime>>,
+}
+
+struct ManualTime {
+    now: Instant,
+    sleepers: Vec<(Instant, task::Waker)>,
+}
+
+impl ManualClock {
+    /// Creates a clock that starts at the current time.
+    pub fn new() -> ManualClock {
+        ManualClock {
+            inner: Arc::new(Mutex::new(ManualTime {
+                now: Instant::now(),
+                sleepers: vec!(),
+            })),
+        }
+    }
+
+    /// Moves the clock forward and wakes the tasks that sleep until then.
+    pub fn advance(&self, duration: Duration) {
+        let mut t = self.inner.lock().unwrap();
+        t.now += duration;
+        let now = t.now;
+        let (ready, waiting) = t.sleepers.drain(..).partition(|(d, _)| *d <= now);
+        t.sleepers = waiting;
+        drop(t);
+        for (_, w) in ready {
+            w.wake();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(ManualSleep {
+            clock: self.clone(),
+            deadline: self.now() + duration,
+        })
+    }
+}
+
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+struct ManualSleep {
+    clock: ManualClock,
+    deadline: Instant,
+}
+
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+impl Future for ManualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<()> {
+        let mut t = self.clock.inner.lock().unwrap();
+        if t.now >= self.deadline {
+            task::Poll::Ready(())
+        } else {
+            t.sleepers.push((self.deadline, cx.waker().clone()));
+            task::Poll::Pending
+        }
+    }
+}
+
+/// Creates a source where the test decides exactly which items that are
+/// ready. The source is pending when no items have been pushed, and ends
+/// when the handle is closed or dropped.
+///
+/// # Example
+/// ```
+/// use spbench::*;
+/// use spbench::testing::*;
+/// use std::task::Poll;
+///
+/// let (script, source) = scripted();
+/// let mut buffer = StreamBuffer::new(source, strategies::Sum);
+///
+/// assert_eq!(poll_once(&mut buffer), Poll::Pending);
+/// script.push_all(vec!(1, 2, 3));
+/// assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(6)));
+/// script.close();
+/// assert_eq!(poll_once(&mut buffer), Poll::Ready(None));
+/// ```
+pub fn scripted<T>() -> (Script<T>, ScriptedSource<T>) {
+    let inner = Arc::new(Mutex::new(ScriptQueue {
+        items: VecDeque::new(),
+        closed: false,
+        waker: None,
+    }));
+    (Script { inner: inner.clone() }, ScriptedSource { inner })
+}
+
+struct ScriptQueue<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    waker: Option<task::Waker>,
+}
+
+/// Pushes items to a ScriptedSource.
+pub struct Script<T> {
+    inner: Arc<Mutex<ScriptQueue<T>>>,
+}
+
+impl<T> Script<T> {
+    /// Makes the item ready in the source.
+    pub fn push(&self, item: T) {
+        self.push_all(Some(item));
+    }
+
+    /// Makes all items ready in the source.
+    pub fn push_all<I: IntoIterator<Item = T>>(&self, items: I) {
+        let mut q = self.inner.lock().unwrap();
+        q.items.extend(items);
+        if let Some(w) = q.waker.take() {
+            w.wake();
+        }
+    }
+
+    /// Ends the source after the ready items.
+    pub fn close(&self) {
+        let mut q = self.inner.lock().unwrap();
+        q.closed = true;
+        if let Some(w) = q.waker.take() {
+            w.wake();
+        }
+    }
+}
+
+impl<T> Drop for Script<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// The source returned by scripted.
+pub struct ScriptedSource<T> {
+    inner: Arc<Mutex<ScriptQueue<T>>>,
+}
+
+impl<T> ScriptedSource<T> {
+    /// The number of ready items.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Stream for ScriptedSource<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<T>> {
+        let mut q = self.inner.lock().unwrap();
+        match q.items.pop_front() {
+            Some(x) => task::Poll::Ready(Some(x)),
+            None if q.closed => task::Poll::Ready(None),
+            None => {
+                q.waker = Some(cx.waker().clone());
+                task::Poll::Pending
+            }
+        }
+    }
+}
+
+/// Polls the stream once with a waker that does nothing. Use this to see
+/// exactly what the stream returns at each step in a test.
+pub fn poll_once<S: Stream + Unpin>(stream: &mut S) -> task::Poll<Option<S::Item>> {
+    let waker = futures::task::noop_waker();
+    let mut cx = task::Context::from_waker(&waker);
+    Pin::new(stream).poll_next(&mut cx)
+}
+
+
+#[cfg(test)]
+mod tests_testing {
+    use super::*;
+    use crate::*;
+    use std::task::Poll;
+
+    #[test]
+    fn scripted_merge() {
+        let (script, source) = scripted();
+        let mut buffer = StreamBuffer::new(source, strategies::Sum);
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+        script.push_all(vec!(1, 2, 3));
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(6)));
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+        script.push(4);
+        script.close();
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(4)));
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(None));
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn manual_debounce() {
+        let clock = ManualClock::new();
+        let (script, source) = scripted();
+        let mut buffer = StreamBuffer::new(source, strategies::Sum)
+            .debounce(Duration::from_millis(10))
+            .with_clock(clock.clone());
+        script.push_all(vec!(1, 2));
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+        clock.advance(Duration::from_millis(5));
+        script.push(3);
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(6)));
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn manual_max_latency() {
+        let clock = ManualClock::new();
+        let (script, source) = scripted();
+        let mut buffer = StreamBuffer::new(source, strategies::Sum)
+            .max_latency(Duration::from_millis(10))
+            .with_clock(clock.clone());
+        for x in 1..5 {
+            script.push(x);
+            assert_eq!(poll_once(&mut buffer), Poll::Pending);
+            clock.advance(Duration::from_millis(3));
+        }
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(10)));
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn max_latency_when_items_keep_arriving() {
+        use futures::prelude::*;
+        let clock = ManualClock::new();
+        let (script, source) = scripted();
+        let mut buffer = StreamBuffer::new(source, strategies::Sum)
+            .max_latency(Duration::from_millis(10))
+            .with_clock(clock.clone());
+        script.push(1);
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+        clock.advance(Duration::from_millis(5));
+        script.push(2);
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+        clock.advance(Duration::from_millis(6));
+        script.push_all(vec!(3, 4));
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some(3)));
+        assert_eq!(poll_once(&mut buffer), Poll::Pending);
+
+        // a source that is always ready, with one item per ms
+        let c = clock.clone();
+        let xs = stream::iter(1..).inspect(move |_| c.advance(Duration::from_millis(1)));
+        let mut buffer = StreamBuffer::new(xs, strategies::Sum)
+            .max_latency(Duration::from_millis(10))
+            .with_clock(clock.clone());
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some((1..=11).sum())));
+        assert_eq!(poll_once(&mut buffer), Poll::Ready(Some((12..=22).sum())));
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn manual_sleep_woken_by_advance() {
+        let clock = ManualClock::new();
+        crate::rt::test::block_on(async {
+            let sleep = clock.sleep(Duration::from_millis(10));
+            let c = clock.clone();
+            let advance = async move { c.advance(Duration::from_millis(10)) };
+            futures::join!(sleep, advance);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests_properties {
+    use super::*;
+    use crate::*;
+    use proptest::prelude::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+    use std::task::Poll;
+
+    /// A run of contiguous inputs folded into one item.
+    #[derive(Debug, Clone, PartialEq)]
+    struct Run {
+        first: usize,
+        last: usize,
+        sum: u64,
+        barrier: bool,
+    }
+
+    /// Pushes each chunk to the source and then polls at most the given 
+    /// number of times, to model a consumer that falls behind. Returns all 
+    /// items after the source has been closed.
+    fn run_script<T, F>(chunks: Vec<(Vec<T>, usize)>, merger: F, max_items: Option<usize>) -> Vec<T> 
+    where 
+        F: Merger<T> 
+    {
+        let (script, source) = scripted();
+        let mut buffer = StreamBuffer::new(source, merger);
+        if let Some(m) = max_items {
+            buffer = buffer.max_items(m);
+        }
+        let mut res = vec!();
+        for (chunk, polls) in chunks {
+            script.push_all(chunk);
+            for _ in 0..polls {
+                match poll_once(&mut buffer) {
+                    Poll::Ready(Some(x)) => res.push(x),
+                    _ => break,
+                }
+            }
+        }
+        script.close();
+        while let Poll::Ready(Some(x)) = poll_once(&mut buffer) {
+            res.push(x);
+        }
+        res
+    }
+
+    fn to_runs(chunks: Vec<(Vec<(u64, bool)>, usize)>) -> (Vec<Run>, Vec<(Vec<Run>, usize)>) {
+        let mut inputs = vec!();
+        let chunks = chunks.into_iter().map(|(xs, polls)| {
+            let runs: Vec<Run> = xs.into_iter().map(|(sum, barrier)| {
+                let i = inputs.len();
+                let run = Run { first: i, last: i, sum, barrier };
+                inputs.push(run.clone());
+                run
+            }).collect();
+            (runs, polls)
+        }).collect();
+        (inputs, chunks)
+    }
+
+    fn chunks<T: std::fmt::Debug>(item: impl Strategy<Value = T>) -> impl Strategy<Value = Vec<(Vec<T>, usize)>> {
+        prop::collection::vec((prop::collection::vec(item, 0..10), 0usize..4), 0..10)
+    }
+
+    fn state(values: &BTreeMap<usize, i32>) -> SPState {
+        let xs: Vec<(SPPath, SPValue)> = values.iter()
+            .map(|(p, v)| (SPPath::from_string(&format!("robot/{}", p)), v.to_spvalue()))
+            .collect();
+        SPState::new_from_values(&xs)
+    }
+
+    proptest! {
+        #[test]
+        fn runs_are_contiguous_folds(
+            chunks in chunks((0u64..100, prop::bool::weighted(0.2))), 
+            max_items in prop::option::of(1usize..5)
+        ) {
+            let (inputs, chunks) = to_runs(chunks);
+            let calls = Rc::new(RefCell::new(vec!()));
+            let c = calls.clone();
+            let res = run_script(chunks, move |b: &mut Run, x: &Run| {
+                c.borrow_mut().push((b.clone(), x.clone(), !x.barrier));
+                if x.barrier {
+                    return false;
+                }
+                b.last = x.last;
+                b.sum += x.sum;
+                true
+            }, max_items);
+
+            // no item lost
+            prop_assert_eq!(res.iter().map(|r| r.sum).sum::<u64>(), inputs.iter().map(|r| r.sum).sum::<u64>());
+
+            // each item is a fold of the next contiguous run of inputs
+            let mut next = 0;
+            for r in &res {
+                prop_assert_eq!(r.first, next);
+                prop_assert!(r.last >= r.first);
+                prop_assert_eq!(r.sum, inputs[r.first..=r.last].iter().map(|x| x.sum).sum::<u64>());
+                prop_assert!(inputs[r.first + 1..=r.last].iter().all(|x| !x.barrier));
+                if let Some(m) = max_items {
+                    prop_assert!(r.last - r.first < m);
+                }
+                next = r.last + 1;
+            }
+            prop_assert_eq!(next, inputs.len());
+
+            // the merge fn is only called with the next input, and never 
+            // across an input where it returned false
+            let calls = calls.borrow();
+            for (i, (b, x, merged)) in calls.iter().enumerate() {
+                prop_assert_eq!(x.first, b.last + 1);
+                prop_assert!(inputs[b.first + 1..=b.last].iter().all(|x| !x.barrier));
+                if !merged {
+                    // the rejected input starts the next item, and the next 
+                    // call is with it as the buffer if more was folded into it
+                    let r = res.iter().find(|r| r.first == x.first);
+                    prop_assert!(r.is_some());
+                    if let Some((next, _, _)) = calls.get(i + 1) {
+                        if r.unwrap().last > x.last {
+                            prop_assert_eq!(next.first, x.first);
+                        }
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn order_preserved_without_merge(chunks in chunks(0u64..100)) {
+            let inputs: Vec<u64> = chunks.iter().flat_map(|(xs, _)| xs.clone()).collect();
+            let res = run_script(chunks, |_: &mut u64, _: &u64| false, None);
+            prop_assert_eq!(res, inputs);
+        }
+
+        #[test]
+        fn state_updates_not_lost(chunks in chunks(prop::collection::btree_map(0usize..4, any::<i32>(), 1..4))) {
+            let inputs: Vec<SPState> = chunks.iter().flat_map(|(xs, _)| xs.iter().map(state)).collect();
+            let chunks = chunks.iter().map(|(xs, polls)| (xs.iter().map(state).collect(), *polls)).collect();
+            let res = run_script(chunks, |b: &mut SPState, x: &SPState| Mergeable::merge(b, x), None);
+            prop_assert!(res.len() <= inputs.len());
+            prop_assert_eq!(res.is_empty(), inputs.is_empty());
+
+            let mut expected = SPState::new();
+            inputs.iter().for_each(|x| expected.extend_from(x));
+            let mut merged = SPState::new();
+            res.iter().for_each(|x| merged.extend_from(x));
+            for p in 0..4 {
+                let p = SPPath::from_string(&format!("robot/{}", p));
+                prop_assert_eq!(merged.sp_value_from_path(&p), expected.sp_value_from_path(&p));
+            }
+        }
+    }
+}
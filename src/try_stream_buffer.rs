@@ -0,0 +1,217 @@
+//! The TryStreamBuffer folds items using a merge fn that can fail
+
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task;
+use pin_project_lite::pin_project;
+use std::error;
+use std::fmt;
+
+/// The merge fn used by the TryStreamBuffer. Merge the item into the buffer 
+/// and return Ok(true), return Ok(false) if the item should not be merged, or 
+/// return an error if the items can not be merged. The buffer should not be
+/// changed if an error is returned.
+/// 
+/// TryMerger is implemented for all FnMut(&mut T, &T) -> Result<bool, E>.
+pub trait TryMerger<T> {
+    type Error;
+    fn try_merge(&mut self, buffer: &mut T, item: &T) -> Result<bool, Self::Error>;
+}
+
+impl<T, E, F> TryMerger<T> for F where F: FnMut(&mut T, &T) -> Result<bool, E> {
+    type Error = E;
+    fn try_merge(&mut self, buffer: &mut T, item: &T) -> Result<bool, E> {
+        self(buffer, item)
+    }
+}
+
+/// Returned by the TryStreamBuffer when the merge fn fails. Includes both 
+/// the buffered item and the item that could not be merged, so nothing 
+/// is lost.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MergeError<T, E> {
+    pub buffer: T,
+    pub item: T,
+    pub error: E,
+}
+
+impl<T, E> fmt::Display for MergeError<T, E> where E: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not merge items: {}", self.error)
+    }
+}
+
+impl<T, E> error::Error for MergeError<T, E> where T: fmt::Debug, E: error::Error + 'static {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+pin_project! {
+    /// TryStreamBuffer is a StreamBuffer where the merge fn can fail. When the 
+    /// merge fn returns an error, a MergeError with the buffered item, the item
+    /// and the error is returned, and a new buffer is started with the next item.
+    /// 
+    /// # Example
+    /// ```
+    /// use futures::{executor::block_on, prelude::*};
+    /// use spbench::*;
+    ///
+    /// let xs = stream::iter(vec!(1, 2, 0, 3));
+    /// let buffer = TryStreamBuffer::new(
+    ///     xs,
+    ///     |x: &mut usize, y: &usize| {
+    ///         if *y == 0 {
+    ///             Err(SPError::No("Can not merge 0".to_string()))
+    ///         } else {
+    ///             *x += y;
+    ///             Ok(true)
+    ///         }
+    ///     }
+    /// );
+    ///
+    /// block_on(async move {
+    ///     let res: Vec<_> = buffer.collect().await;
+    ///     assert_eq!(res[0].as_ref().unwrap_err().buffer, 3);
+    ///     assert_eq!(res[0].as_ref().unwrap_err().item, 0);
+    ///     assert_eq!(res[1], Ok(3));
+    /// });
+    /// ```
+    pub struct TryStreamBuffer<S, F> where S: Stream, F: TryMerger<S::Item> {
+        #[pin] stream: S,
+        merger: F, 
+        buffer: Option<S::Item>,
+        done: bool,
+    }
+}
+
+impl<S, F> TryStreamBuffer<S, F> where S: Stream, F: TryMerger<S::Item> {
+    /// Creates a new TryStreamBuffer that reads from the stream and folds the 
+    /// ready items using merger.
+    pub fn new(stream: S, merger: F) -> TryStreamBuffer<S, F> {
+        TryStreamBuffer{
+            stream, 
+            merger,
+            buffer: None,
+            done: false,
+        }
+    }
+}
+
+impl<S, F> Stream for TryStreamBuffer<S, F> where S: Stream, F: TryMerger<S::Item> {
+    type Item = Result<S::Item, MergeError<S::Item, F::Error>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if *this.done {
+                return task::Poll::Ready(None);
+            }
+            match this.stream.as_mut().poll_next(cx) {
+                task::Poll::Ready(Some(x)) => {
+                    let buffer = match this.buffer.as_mut() {
+                        Some(buffer) => buffer,
+                        None => {
+                            *this.buffer = Some(x);
+                            continue;
+                        }
+                    };
+                    match this.merger.try_merge(buffer, &x) {
+                        Ok(true) => {},
+                        Ok(false) => {
+                            return task::Poll::Ready(this.buffer.replace(x).map(Ok));
+                        }
+                        Err(error) => {
+                            let e = MergeError {
+                                buffer: this.buffer.take().unwrap(),
+                                item: x,
+                                error,
+                            };
+                            return task::Poll::Ready(Some(Err(e)));
+                        }
+                    }
+                }
+                task::Poll::Ready(None) => {
+                    *this.done = true;
+                    return task::Poll::Ready(this.buffer.take().map(Ok));
+                }
+                task::Poll::Pending if this.buffer.is_none() => {
+                    return task::Poll::Pending;
+                }
+                task::Poll::Pending => {
+                    return task::Poll::Ready(this.buffer.take().map(Ok));
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests_try_stream_buffer {
+    use super::*;
+    use crate::*;
+    use futures::prelude::*;
+    use crate::rt::test::*;
+
+    /// Merges the states, but fails if both states have different next 
+    /// values for a variable
+    fn merge_next(x: &mut SPState, y: &SPState) -> SPResult<bool> {
+        for (p, v) in y.projection().projection {
+            let conflict = x
+                .state_value_from_path(p)
+                .map(|xv| xv.has_next() && v.has_next() && xv.next_value() != v.next_value())
+                .unwrap_or(false);
+            if conflict {
+                return Err(SPError::No(format!("Conflicting next values for {}", p)));
+            }
+        }
+        x.extend_from(y);
+        Ok(true)
+    }
+
+    fn next_state(value: i32) -> SPState {
+        let ab = SPPath::from_slice(&["a", "b"]);
+        let mut s = state!(ab => 0);
+        s.next_from_path(&ab, value.to_spvalue()).unwrap();
+        s
+    }
+
+    #[test]
+    fn conflicting_states() {
+        block_on(async {
+            let ac = SPPath::from_slice(&["a", "c"]);
+            let xs = stream::iter(vec!(
+                state!(ac => true),
+                next_state(1),
+                next_state(2),
+                state!(ac => false),
+            ));
+            let res: Vec<_> = TryStreamBuffer::new(xs, merge_next).collect().await;
+            assert_eq!(res.len(), 2);
+
+            let e = res[0].clone().unwrap_err();
+            assert_eq!(e.buffer.sp_value_from_path(&ac), Some(&true.to_spvalue()));
+            assert_eq!(e.item, next_state(2));
+            assert!(e.to_string().contains("a/b"));
+
+            let s = res[1].clone().unwrap();
+            assert_eq!(s.sp_value_from_path(&ac), Some(&false.to_spvalue()));
+        });
+    }
+
+    #[test]
+    fn source_not_polled_after_end() {
+        block_on(async {
+            let xs = stream::unfold(1, |n| async move {
+                if n < 4 { Some((n, n + 1)) } else { None }
+            });
+            let buffer = TryStreamBuffer::new(xs, |x: &mut usize, y: &usize| -> SPResult<bool> {
+                *x += y;
+                Ok(true)
+            });
+            let res: Vec<_> = buffer.collect().await;
+            assert_eq!(res, vec!(Ok(6)));
+        });
+    }
+}